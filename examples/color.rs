@@ -14,39 +14,48 @@ use xencelabs_quick_keys::*;
 fn run(api: HidApi) -> QKResult<()> {
     match QKDevice::open(api, ConnectionMode::Auto) {
         Ok(dev) => {
-            dev.set_screen_orientation(ScreenOrientation::Rotate270)?;
-            dev.set_screen_brightness(ScreenBrightness::Medium)?;
-            dev.set_wheel_speed(WheelSpeed::Normal)?;
-            dev.set_sleep_timeout(1)?;
-            dev.set_ring_color(255, 255, 255)?;
-            dev.set_key_text(0, "red")?;
-            dev.set_key_text(1, "green")?;
-            dev.set_key_text(2, "blue")?;
-            dev.set_key_text(3, "yellow")?;
-            dev.set_key_text(4, "purple")?;
-            dev.set_key_text(5, "turquoise")?;
-            dev.set_key_text(6, "white")?;
-            dev.set_key_text(7, "off")?;
+            let config = DeviceConfig {
+                orientation: Some(ScreenOrientation::Rotate270),
+                brightness: Some(ScreenBrightness::Medium),
+                wheel_speed: Some(WheelSpeed::Normal),
+                sleep_timeout: Some(1),
+                ring_color: Some(Color::WHITE),
+                key_texts: ["red", "green", "blue", "yellow", "purple", "turquoise", "white", "off"]
+                    .map(|t| Some(t.to_string())),
+            };
+            let report = dev.apply(&config);
+            if let Some((_, e)) = report.failed.into_iter().next() {
+                return Err(e);
+            }
             thread::sleep(time::Duration::from_millis(1000));
             dev.show_overlay_text("Disco, disco!", 3)?;
+            let mut battery = BatteryMonitor::new();
             loop {
                 match dev.read() {
-                    Ok(ev) => match ev {
-                        Event::Wheel { direction: WheelDirection::Left } => dev.set_ring_color(255, 0, 0),
-                        Event::Wheel { direction: WheelDirection::Right } => dev.set_ring_color(0, 255, 0),
-                        Event::Button { state: ButtonState { button_wheel: true, .. } } => dev.set_ring_color(0, 0, 255),
-                        Event::Button { state: ButtonState { button_0: true, .. } } => dev.set_ring_color(255, 0, 0),
-                        Event::Button { state: ButtonState { button_1: true, .. } } => dev.set_ring_color(0, 255, 0),
-                        Event::Button { state: ButtonState { button_2: true, .. } } => dev.set_ring_color(0, 0, 255),
-                        Event::Button { state: ButtonState { button_3: true, .. } } => dev.set_ring_color(255, 255, 0),
-                        Event::Button { state: ButtonState { button_4: true, .. } } => dev.set_ring_color(255, 0, 255),
-                        Event::Button { state: ButtonState { button_5: true, .. } } => dev.set_ring_color(0, 255, 255),
-                        Event::Button { state: ButtonState { button_6: true, .. } } => dev.set_ring_color(255, 255, 255),
-                        Event::Button { state: ButtonState { button_7: true, .. } } => dev.set_ring_color(0, 0, 0),
+                    Ok(None) => Ok(()),
+                    Ok(Some(ev)) => match ev {
+                        Event::Wheel { direction: WheelDirection::Left, .. } => dev.set_ring_color(Color::RED),
+                        Event::Wheel { direction: WheelDirection::Right, .. } => dev.set_ring_color(Color::LIME),
+                        Event::Button { state: ButtonState { button_wheel: true, .. } } => dev.set_ring_color(Color::BLUE),
+                        Event::Button { state: ButtonState { button_0: true, .. } } => dev.set_ring_color(Color::RED),
+                        Event::Button { state: ButtonState { button_1: true, .. } } => dev.set_ring_color(Color::LIME),
+                        Event::Button { state: ButtonState { button_2: true, .. } } => dev.set_ring_color(Color::BLUE),
+                        Event::Button { state: ButtonState { button_3: true, .. } } => dev.set_ring_color(Color::YELLOW),
+                        Event::Button { state: ButtonState { button_4: true, .. } } => dev.set_ring_color(Color::MAGENTA),
+                        Event::Button { state: ButtonState { button_5: true, .. } } => dev.set_ring_color(Color::CYAN),
+                        Event::Button { state: ButtonState { button_6: true, .. } } => dev.set_ring_color(Color::WHITE),
+                        Event::Button { state: ButtonState { button_7: true, .. } } => dev.set_ring_color(Color::BLACK),
                         Event::Button { state: ButtonState { button_extra: true, .. } } => dev.show_overlay_text("Disco, disco!", 3),
                         Event::Button { state: ButtonState { .. } } => { println!("release"); Ok(()) },
                         Event::Unknown { data: d } => { println!("unknown! {:?}", d); Ok(()) },
-                        Event::Battery { percent: p } => { println!("battery level: {:?}", p); Ok(()) },
+                        Event::Disconnected => { println!("disconnected!"); Ok(()) },
+                        Event::Battery { percent: p } => {
+                            println!("battery level: {:?}", p);
+                            for alert in battery.observe(&ev) {
+                                println!("battery alert: {:?}", alert);
+                            }
+                            Ok(())
+                        },
                     },
                     Err(e) => Err(e),
                 }?;
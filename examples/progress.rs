@@ -7,23 +7,27 @@ use hidapi::HidApi;
 use xencelabs_quick_keys::*;
 
 fn run(api: HidApi) -> QKResult<()> {
-    let mut progress = 0;
+    let mut progress: usize = 0;
     
     match QKDevice::open(api, ConnectionMode::Auto) {
         Ok(dev) => {
             dev.set_screen_orientation(ScreenOrientation::Rotate270)?;
             dev.set_screen_brightness(ScreenBrightness::Medium)?;
             dev.set_wheel_speed(WheelSpeed::Faster)?;
-            dev.set_ring_color(0, 0, 0)?;
+            dev.set_ring_color(Color::BLACK)?;
             loop {
                 match dev.read() {
-                    Ok(ev) => match ev {
-                        Event::Wheel { direction: WheelDirection::Left } => {
-                            progress = if progress > 0 { progress - 1 } else { 0 };
+                    Ok(None) => Ok(()),
+                    Ok(Some(ev)) => match ev {
+                        // Holding the wheel button while turning makes coarse adjustments
+                        Event::Wheel { direction: WheelDirection::Left, state } => {
+                            let step = if state.button_wheel { 10 } else { 1 };
+                            progress = progress.saturating_sub(step);
                             dev.show_overlay_text(format!("[{:_<30}]", "=".repeat(progress*30/100).to_string()).as_str(), 1)
                         },
-                        Event::Wheel { direction: WheelDirection::Right } => {
-                            progress = if progress < 100 { progress + 1 } else { 100 };
+                        Event::Wheel { direction: WheelDirection::Right, state } => {
+                            let step = if state.button_wheel { 10 } else { 1 };
+                            progress = if progress + step < 100 { progress + step } else { 100 };
                             dev.show_overlay_text(format!("[{:_<30}]", "=".repeat(progress*30/100).to_string()).as_str(), 1)
                         },
                         _ => Ok(()),
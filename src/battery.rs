@@ -0,0 +1,275 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::msgs::Event;
+
+/// A battery level reported by the device at a given moment
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct BatteryReading {
+    pub percent: u8,
+    pub at: Instant,
+}
+
+/// Notification emitted by a `BatteryMonitor` when the level crosses one of its thresholds
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BatteryAlert {
+    /// The level dropped to (or below) the threshold.
+    Low { threshold: u8, percent: u8 },
+    /// The level went back above the threshold (the device is charging).
+    Recovered { threshold: u8, percent: u8 },
+}
+
+/// Keep track of the battery level reported by the device.
+///
+/// Feed it every `Event` read from the device (`observe`), it remembers the last reading,
+/// keeps a bounded history of readings and reports threshold crossings.
+#[derive(Debug, Clone)]
+pub struct BatteryMonitor {
+    thresholds: Vec<u8>,
+    history: VecDeque<BatteryReading>,
+    capacity: usize,
+}
+
+impl Default for BatteryMonitor {
+    fn default() -> Self {
+        BatteryMonitor::new()
+    }
+}
+
+impl BatteryMonitor {
+    /// A monitor with the default thresholds (20%, 10% and 5%).
+    pub fn new() -> Self {
+        BatteryMonitor::with_thresholds(&[20, 10, 5])
+    }
+
+    /// A monitor alerting when the level crosses any of the given percentages.
+    pub fn with_thresholds(thresholds: &[u8]) -> Self {
+        let mut thresholds = thresholds.to_vec();
+        thresholds.sort_unstable_by(|a, b| b.cmp(a));
+        thresholds.dedup();
+        BatteryMonitor {
+            thresholds,
+            history: VecDeque::new(),
+            capacity: 1024,
+        }
+    }
+
+    /// Set the maximum number of readings kept in the history (oldest are dropped first).
+    pub fn set_history_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+        while self.history.len() > self.capacity {
+            self.history.pop_front();
+        }
+    }
+
+    /// Process an event read from the device.  Non battery events are ignored.
+    pub fn observe(&mut self, event: &Event) -> Vec<BatteryAlert> {
+        match event {
+            Event::Battery { percent } => self.record(*percent, Instant::now()),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Record a battery reading taken at the given moment, returning the crossed thresholds.
+    pub fn record(&mut self, percent: u8, at: Instant) -> Vec<BatteryAlert> {
+        let previous = self.battery_percent();
+        let alerts = self
+            .thresholds
+            .iter()
+            .filter_map(|&threshold| {
+                let was_low = previous.map(|p| p <= threshold);
+                let is_low = percent <= threshold;
+                match (was_low, is_low) {
+                    (None | Some(false), true) => Some(BatteryAlert::Low { threshold, percent }),
+                    (Some(true), false) => Some(BatteryAlert::Recovered { threshold, percent }),
+                    _ => None,
+                }
+            })
+            .collect();
+
+        if self.history.len() == self.capacity {
+            self.history.pop_front();
+        }
+        self.history.push_back(BatteryReading { percent, at });
+        alerts
+    }
+
+    /// The last battery level reported by the device (if any).
+    pub fn battery_percent(&self) -> Option<u8> {
+        self.last_reading().map(|r| r.percent)
+    }
+
+    /// The last battery reading (if any).
+    pub fn last_reading(&self) -> Option<BatteryReading> {
+        self.history.back().copied()
+    }
+
+    /// All the readings kept so far, oldest first.
+    pub fn history(&self) -> impl Iterator<Item = &BatteryReading> {
+        self.history.iter()
+    }
+
+    /// Discharge rate in percent per hour, computed over the readings taken since the
+    /// battery stopped charging.  `None` if there is not enough data or it is not discharging.
+    pub fn discharge_rate(&self) -> Option<f32> {
+        // Only the readings after the last increase of the level belong to the current discharge.
+        let start = (1..self.history.len())
+            .rev()
+            .find(|&i| self.history[i].percent > self.history[i - 1].percent)
+            .unwrap_or(0);
+        let samples = self.history.range(start..).collect::<Vec<_>>();
+        let first = samples.first()?;
+        if samples.len() < 2 {
+            return None;
+        }
+
+        // Least squares slope of percent over time (in hours).
+        let points = samples
+            .iter()
+            .map(|r| {
+                let hours = r.at.saturating_duration_since(first.at).as_secs_f32() / 3600.0;
+                (hours, r.percent as f32)
+            })
+            .collect::<Vec<(f32, f32)>>();
+        let n = points.len() as f32;
+        let mean_t = points.iter().map(|(t, _)| t).sum::<f32>() / n;
+        let mean_p = points.iter().map(|(_, p)| p).sum::<f32>() / n;
+        let variance = points
+            .iter()
+            .map(|(t, _)| (t - mean_t).powi(2))
+            .sum::<f32>();
+        if variance == 0.0 {
+            return None;
+        }
+        let covariance = points
+            .iter()
+            .map(|(t, p)| (t - mean_t) * (p - mean_p))
+            .sum::<f32>();
+        let rate = -covariance / variance;
+        (rate > 0.0).then_some(rate)
+    }
+
+    /// Estimated time until the battery is empty, based on the current discharge rate.
+    /// `None` too if the battery is discharging too slowly for the estimate to fit a `Duration`.
+    pub fn estimate_remaining(&self) -> Option<Duration> {
+        let rate = self.discharge_rate()?;
+        let percent = self.battery_percent()? as f32;
+        Duration::try_from_secs_f32(percent / rate * 3600.0).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests_battery {
+    use super::*;
+
+    fn minutes(m: u64) -> Duration {
+        Duration::from_secs(m * 60)
+    }
+
+    #[test]
+    fn it_should_remember_the_last_reading() {
+        let mut monitor = BatteryMonitor::new();
+        assert_eq!(monitor.battery_percent(), None);
+        monitor.observe(&Event::Battery { percent: 80 });
+        monitor.observe(&Event::Wheel {
+            direction: crate::msgs::WheelDirection::Left,
+            state: Default::default(),
+        });
+        assert_eq!(monitor.battery_percent(), Some(80));
+        assert_eq!(monitor.history().count(), 1);
+    }
+
+    #[test]
+    fn it_should_alert_when_crossing_thresholds() {
+        let now = Instant::now();
+        let mut monitor = BatteryMonitor::with_thresholds(&[10, 20]);
+        assert_eq!(monitor.record(50, now), vec![]);
+        assert_eq!(
+            monitor.record(20, now),
+            vec![BatteryAlert::Low {
+                threshold: 20,
+                percent: 20
+            }]
+        );
+        assert_eq!(monitor.record(15, now), vec![]);
+        assert_eq!(
+            monitor.record(9, now),
+            vec![BatteryAlert::Low {
+                threshold: 10,
+                percent: 9
+            }]
+        );
+        assert_eq!(
+            monitor.record(30, now),
+            vec![
+                BatteryAlert::Recovered {
+                    threshold: 20,
+                    percent: 30
+                },
+                BatteryAlert::Recovered {
+                    threshold: 10,
+                    percent: 30
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn it_should_alert_on_a_low_first_reading() {
+        let mut monitor = BatteryMonitor::with_thresholds(&[10]);
+        assert_eq!(
+            monitor.record(5, Instant::now()),
+            vec![BatteryAlert::Low {
+                threshold: 10,
+                percent: 5
+            }]
+        );
+    }
+
+    #[test]
+    fn it_should_bound_the_history() {
+        let now = Instant::now();
+        let mut monitor = BatteryMonitor::new();
+        monitor.set_history_capacity(2);
+        for percent in [90, 80, 70] {
+            monitor.record(percent, now);
+        }
+        let kept = monitor.history().map(|r| r.percent).collect::<Vec<u8>>();
+        assert_eq!(kept, vec![80, 70]);
+    }
+
+    #[test]
+    fn it_should_estimate_remaining_time() {
+        let start = Instant::now();
+        let mut monitor = BatteryMonitor::new();
+        monitor.record(60, start);
+        monitor.record(55, start + minutes(30));
+        monitor.record(50, start + minutes(60));
+        assert_eq!(monitor.discharge_rate().map(f32::round), Some(10.0));
+        let remaining = monitor.estimate_remaining().unwrap();
+        assert!((299..=300).contains(&(remaining.as_secs() / 60)));
+    }
+
+    #[test]
+    fn it_should_not_estimate_beyond_what_a_duration_holds() {
+        let start = Instant::now();
+        let mut monitor = BatteryMonitor::new();
+        monitor.record(100, start);
+        monitor.record(99, start + Duration::from_secs(1_000_000_000_000_000_000));
+        assert!(monitor.discharge_rate().is_some());
+        assert_eq!(monitor.estimate_remaining(), None);
+    }
+
+    #[test]
+    fn it_should_not_estimate_while_charging() {
+        let start = Instant::now();
+        let mut monitor = BatteryMonitor::new();
+        monitor.record(50, start);
+        assert_eq!(monitor.estimate_remaining(), None);
+        monitor.record(60, start + minutes(30));
+        assert_eq!(monitor.estimate_remaining(), None);
+        monitor.record(59, start + minutes(40));
+        assert!(monitor.estimate_remaining().is_some());
+    }
+}
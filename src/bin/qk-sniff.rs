@@ -0,0 +1,228 @@
+//! Protocol sniffer for the Quick Keys.
+//!
+//! Logs every report sent by the device in hex, decoding the fields understood by
+//! `process_input` and highlighting the bytes that are not.  Lines typed on the standard
+//! input are sent to the device as raw frames, e.g. `02 b1 0a 01 03` (missing bytes are
+//! zero-filled up to 32).
+
+use std::io::BufRead;
+use std::sync::mpsc;
+use std::time::Instant;
+
+use hidapi::HidApi;
+
+use xencelabs_quick_keys::*;
+
+const USAGE: &str = "Usage: qk-sniff [--mode auto|wired|wireless] [--no-subscribe] [--no-color]
+
+Logs every report sent by the device in annotated hex and sends the hex frames
+typed on the standard input (e.g. `02 b1 0a 01 03`) to the device.";
+
+const HIGHLIGHT: &str = "\x1b[1;33m";
+const RESET: &str = "\x1b[0m";
+
+struct Args {
+    mode: ConnectionMode,
+    subscribe: bool,
+    color: bool,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        mode: ConnectionMode::Auto,
+        subscribe: true,
+        color: true,
+    };
+    let mut argv = std::env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--mode" => {
+                args.mode = match argv.next().map(|m| m.parse()) {
+                    Some(Ok(mode)) => mode,
+                    _ => return Err("--mode expects auto, wired or wireless".to_string()),
+                }
+            }
+            "--no-subscribe" => args.subscribe = false,
+            "--no-color" => args.color = false,
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            other => return Err(format!("unexpected argument: {}\n\n{}", other, USAGE)),
+        }
+    }
+    Ok(args)
+}
+
+/// Parse a line of hex bytes, zero-filling it up to a whole frame.
+fn parse_frame(line: &str) -> Result<[u8; 32], String> {
+    let bytes = line
+        .split(|c: char| c.is_whitespace() || c == ',' || c == ':')
+        .filter(|b| !b.is_empty())
+        .map(|b| {
+            let b = b.trim_start_matches("0x");
+            u8::from_str_radix(b, 16).map_err(|_| format!("invalid byte: {}", b))
+        })
+        .collect::<Result<Vec<u8>, String>>()?;
+    if bytes.len() > 32 {
+        return Err(format!("frame too long: {} bytes (max 32)", bytes.len()));
+    }
+    let mut frame = [0u8; 32];
+    frame[..bytes.len()].copy_from_slice(&bytes);
+    Ok(frame)
+}
+
+/// Offsets of the bytes of a report understood by `process_input`.
+fn known_bytes(event: &Event) -> &'static [usize] {
+    match event {
+        Event::Button { .. } | Event::Wheel { .. } => &[0, 1, 2, 3, 7],
+        Event::Battery { .. } => &[0, 1, 2, 3],
+        _ => &[],
+    }
+}
+
+/// Format a report in hex, highlighting the non-zero bytes not understood by `process_input`.
+fn annotate(report: &[u8], event: &Event, color: bool) -> String {
+    let known = known_bytes(event);
+    let hex = report
+        .iter()
+        .enumerate()
+        .map(|(i, b)| {
+            if color && *b != 0 && !known.contains(&i) {
+                format!("{}{:02x}{}", HIGHLIGHT, b, RESET)
+            } else {
+                format!("{:02x}", b)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ");
+    let decoded = match event {
+        Event::Unknown { .. } => "unknown".to_string(),
+        event => format!("{:?}", event),
+    };
+    format!("{}  {}", hex, decoded)
+}
+
+fn run(args: Args) -> QKResult<()> {
+    let subscriptions = if args.subscribe {
+        Subscriptions::all()
+    } else {
+        Subscriptions::none()
+    };
+    let device = OpenOptions::new()
+        .mode(args.mode)
+        .subscriptions(subscriptions)
+        .open(HidApi::new()?)?;
+
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let start = Instant::now();
+    loop {
+        if let Some(report) = device.read_raw_timeout(50)? {
+            let mut buf = [0u8; INPUT_REPORT_SIZE];
+            buf[..report.len()].copy_from_slice(&report);
+            let event = process_input(&buf);
+            println!(
+                "{:>10.3} <  {}",
+                start.elapsed().as_secs_f64(),
+                annotate(&report, &event, args.color)
+            );
+        }
+        for line in rx.try_iter() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            match parse_frame(line) {
+                Ok(frame) => {
+                    // The device may reject a frame, keep sniffing anyway
+                    if let Err(e) = device.send_raw(&frame) {
+                        eprintln!("Error: {}", e);
+                        continue;
+                    }
+                    let decoded = match decode_command(&frame) {
+                        Some(decoded) => format!("{:?}", decoded),
+                        None => "unknown".to_string(),
+                    };
+                    println!(
+                        "{:>10.3}  > {}  {}",
+                        start.elapsed().as_secs_f64(),
+                        frame.map(|b| format!("{:02x}", b)).join(" "),
+                        decoded
+                    );
+                }
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    }
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = run(args) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests_sniff {
+    use super::*;
+
+    #[test]
+    fn it_should_parse_frames() {
+        let frame = parse_frame("02 b1 0a 01 03").unwrap();
+        assert_eq!(frame[..6], [0x02, 0xb1, 0x0a, 0x01, 0x03, 0x00]);
+        assert_eq!(
+            parse_frame("0x02,0xb1:0a").unwrap()[..3],
+            [0x02, 0xb1, 0x0a]
+        );
+        assert_eq!(parse_frame("").unwrap(), [0u8; 32]);
+    }
+
+    #[test]
+    fn it_should_refuse_invalid_frames() {
+        assert!(parse_frame("02 zz").is_err());
+        assert!(parse_frame("102").is_err());
+        assert!(parse_frame(&"00 ".repeat(33)).is_err());
+        assert!(parse_frame(&"00 ".repeat(32)).is_ok());
+    }
+
+    #[test]
+    fn it_should_annotate_reports() {
+        let report = [0x02, 0xf2, 0x01, 0x2a, 0x00, 0x07];
+        let event = Event::Battery { percent: 42 };
+        assert_eq!(
+            annotate(&report, &event, false),
+            "02 f2 01 2a 00 07  Battery { percent: 42 }"
+        );
+        assert_eq!(
+            annotate(&report, &event, true),
+            format!(
+                "02 f2 01 2a 00 {}07{}  Battery {{ percent: 42 }}",
+                HIGHLIGHT, RESET
+            )
+        );
+    }
+
+    #[test]
+    fn it_should_highlight_every_byte_of_unknown_reports() {
+        let mut data = [0u8; INPUT_REPORT_SIZE];
+        data[..2].copy_from_slice(&[0x02, 0xee]);
+        let event = Event::Unknown { data };
+        assert_eq!(
+            annotate(&data[..3], &event, true),
+            format!("{h}02{r} {h}ee{r} 00  unknown", h = HIGHLIGHT, r = RESET)
+        );
+    }
+}
@@ -0,0 +1,254 @@
+//! Command-line tool to drive the Quick Keys from shell scripts.
+
+use hidapi::HidApi;
+
+use xencelabs_quick_keys::*;
+
+const USAGE: &str = "Usage: qk [--mode auto|wired|wireless] <command>
+
+Commands:
+  list                      List the connected devices
+  label <key> <text>        Set the label of a key (0-7)
+  ring <color>              Set the ring color (#rrggbb, rgb(...), hsl(...), CSS name...)
+  overlay <text> [--seconds N]
+                            Show a text overlay (2 seconds by default)
+  brightness <level>        off, low, medium or full
+  orientation <degrees>     0, 90, 180 or 270
+  wheel-speed <speed>       slowest, slower, normal, faster or fastest
+  sleep-timeout <minutes>   Minutes before switching off after losing connection
+  events                    Print the events of the device as JSON lines";
+
+/// A usage error, with the message to show
+struct Usage(String);
+
+enum Action {
+    List,
+    Events,
+    Send(Command),
+}
+
+fn usage<T>(message: impl Into<String>) -> Result<T, Usage> {
+    Err(Usage(message.into()))
+}
+
+fn parse_args(args: &[String]) -> Result<(ConnectionMode, Action), Usage> {
+    let mut mode = ConnectionMode::Auto;
+    let mut seconds = 2;
+    let mut positional = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mode" => {
+                mode = match args.next().map(|m| m.parse()) {
+                    Some(Ok(mode)) => mode,
+                    _ => return usage("--mode expects auto, wired or wireless"),
+                }
+            }
+            "--seconds" => {
+                seconds = match args.next().and_then(|s| s.parse().ok()) {
+                    Some(s) => s,
+                    None => return usage("--seconds expects a number of seconds (0-255)"),
+                }
+            }
+            "-h" | "--help" => return usage(""),
+            _ => positional.push(arg.as_str()),
+        }
+    }
+
+    let action = match positional.as_slice() {
+        ["list"] => Action::List,
+        ["events"] => Action::Events,
+        ["label", key, text] => Action::Send(Command::SetKeyText {
+            key: match key.parse() {
+                Ok(k) => k,
+                Err(_) => return usage(format!("invalid key {:?}, expected 0-7", key)),
+            },
+            text: text.to_string(),
+        }),
+        ["ring", color] => Action::Send(Command::SetRingColor {
+            color: color.parse().or_else(|e| usage(format!("{}", e)))?,
+        }),
+        ["overlay", text] => Action::Send(Command::ShowOverlayText {
+            text: text.to_string(),
+            seconds,
+        }),
+        ["brightness", level] => Action::Send(Command::SetScreenBrightness {
+            level: match *level {
+                "off" => ScreenBrightness::Off,
+                "low" => ScreenBrightness::Low,
+                "medium" => ScreenBrightness::Medium,
+                "full" => ScreenBrightness::Full,
+                _ => return usage("brightness expects off, low, medium or full"),
+            },
+        }),
+        ["orientation", degrees] => Action::Send(Command::SetScreenOrientation {
+            orientation: match *degrees {
+                "0" => ScreenOrientation::Rotate0,
+                "90" => ScreenOrientation::Rotate90,
+                "180" => ScreenOrientation::Rotate180,
+                "270" => ScreenOrientation::Rotate270,
+                _ => return usage("orientation expects 0, 90, 180 or 270"),
+            },
+        }),
+        ["wheel-speed", speed] => Action::Send(Command::SetWheelSpeed {
+            speed: match *speed {
+                "slowest" => WheelSpeed::Slowest,
+                "slower" => WheelSpeed::Slower,
+                "normal" => WheelSpeed::Normal,
+                "faster" => WheelSpeed::Faster,
+                "fastest" => WheelSpeed::Fastest,
+                _ => {
+                    return usage("wheel-speed expects slowest, slower, normal, faster or fastest")
+                }
+            },
+        }),
+        ["sleep-timeout", minutes] => Action::Send(Command::SetSleepTimeout {
+            minutes: match minutes.parse() {
+                Ok(m) => m,
+                Err(_) => return usage("sleep-timeout expects a number of minutes (0-255)"),
+            },
+        }),
+        [] => return usage(""),
+        [command, ..] => return usage(format!("invalid arguments for {:?}", command)),
+    };
+    if let Action::Send(command) = &action {
+        command.validate().or_else(|e| usage(e.to_string()))?;
+    }
+    Ok((mode, action))
+}
+
+fn run(mode: ConnectionMode, action: Action) -> QKResult<()> {
+    let hidapi = HidApi::new()?;
+    match action {
+        Action::List => {
+            for dev in list_devices(&hidapi) {
+                println!(
+                    "{}\t{:?}\t{}",
+                    dev.path,
+                    dev.mode,
+                    dev.serial_number.unwrap_or_default()
+                );
+            }
+        }
+        Action::Events => {
+            let dev = OpenOptions::new().mode(mode).open(hidapi)?;
+            for event in dev.events() {
+                let json = serde_json::to_string(&event?)
+                    .map_err(|e| QKError::QKParseError(e.to_string()))?;
+                println!("{}", json);
+            }
+        }
+        Action::Send(command) => {
+            // Don't subscribe to anything, not to steal events from other programs
+            let dev = OpenOptions::new()
+                .mode(mode)
+                .subscriptions(Subscriptions::none())
+                .open(hidapi)?;
+            dev.send(&command)?;
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    let (mode, action) = match parse_args(&args) {
+        Ok(parsed) => parsed,
+        Err(Usage(message)) => {
+            if !message.is_empty() {
+                eprintln!("{}\n", message);
+            }
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = run(mode, action) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests_qk {
+    use super::*;
+
+    fn parse(args: &str) -> Result<(ConnectionMode, Action), Usage> {
+        let args = args
+            .split_whitespace()
+            .map(String::from)
+            .collect::<Vec<String>>();
+        parse_args(&args)
+    }
+
+    fn command(args: &str) -> Command {
+        match parse(args) {
+            Ok((_, Action::Send(command))) => command,
+            _ => panic!("{:?} is not a command", args),
+        }
+    }
+
+    fn error(args: &str) -> String {
+        match parse(args) {
+            Err(Usage(message)) => message,
+            Ok(_) => panic!("{:?} should be refused", args),
+        }
+    }
+
+    #[test]
+    fn it_should_parse_commands() {
+        assert_eq!(
+            command("label 3 Copy"),
+            Command::SetKeyText {
+                key: 3,
+                text: "Copy".to_string()
+            }
+        );
+        assert_eq!(
+            command("ring red"),
+            Command::SetRingColor { color: Color::RED }
+        );
+        assert_eq!(
+            command("overlay Hello --seconds 5"),
+            Command::ShowOverlayText {
+                text: "Hello".to_string(),
+                seconds: 5
+            }
+        );
+        assert_eq!(
+            command("orientation 180"),
+            Command::SetScreenOrientation {
+                orientation: ScreenOrientation::Rotate180
+            }
+        );
+        assert!(matches!(
+            parse("list"),
+            Ok((ConnectionMode::Auto, Action::List))
+        ));
+        assert!(matches!(
+            parse("--mode wireless events"),
+            Ok((ConnectionMode::Wireless, Action::Events))
+        ));
+    }
+
+    #[test]
+    fn it_should_refuse_invalid_arguments() {
+        assert_eq!(
+            error("--mode usb list"),
+            "--mode expects auto, wired or wireless"
+        );
+        assert_eq!(error("label x Copy"), "invalid key \"x\", expected 0-7");
+        assert_eq!(
+            error("brightness max"),
+            "brightness expects off, low, medium or full"
+        );
+        assert_eq!(error("label 3"), "invalid arguments for \"label\"");
+        assert_eq!(error(""), "");
+    }
+
+    #[test]
+    fn it_should_refuse_commands_the_device_can_not_take() {
+        assert!(error("label 8 Copy").contains("out of range"));
+        let long = format!("overlay {}", "x".repeat(33));
+        assert!(error(&long).contains("longer than 32 bytes"));
+    }
+}
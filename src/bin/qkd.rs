@@ -0,0 +1,54 @@
+//! Daemon owning the Quick Keys, driven by other programs through a Unix socket.
+
+#[cfg(unix)]
+fn main() {
+    use std::path::PathBuf;
+
+    use hidapi::HidApi;
+    use xencelabs_quick_keys::*;
+
+    const USAGE: &str = "Usage: qkd [--mode auto|wired|wireless] [--socket PATH] [--no-subscribe]";
+
+    fn usage() -> ! {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    }
+
+    let mut mode = ConnectionMode::Auto;
+    let mut socket = default_socket_path();
+    let mut subscriptions = Subscriptions::all();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mode" => {
+                mode = match args.next().map(|m| m.parse()) {
+                    Some(Ok(mode)) => mode,
+                    _ => usage(),
+                }
+            }
+            "--socket" => socket = args.next().map(PathBuf::from).unwrap_or_else(|| usage()),
+            "--no-subscribe" => subscriptions = Subscriptions::none(),
+            _ => usage(),
+        }
+    }
+
+    let run = || -> QKResult<()> {
+        let dev = OpenOptions::new()
+            .mode(mode)
+            .subscriptions(subscriptions)
+            .open(HidApi::new()?)?;
+        let mut daemon = Daemon::bind(dev, &socket)?;
+        eprintln!("Listening on {}", socket.display());
+        daemon.run()
+    };
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("qkd needs Unix sockets");
+    std::process::exit(1);
+}
@@ -0,0 +1,118 @@
+use crate::color::Color;
+
+#[cfg(feature = "serde")]
+use crate::error::QKError;
+#[cfg(feature = "serde")]
+use crate::QKResult;
+
+/// Color correction applied to the LED ring before a color is sent to the device.
+///
+/// Each channel goes through a gamma curve, then is scaled by its gain and by the
+/// white point.  The default calibration leaves colors untouched.
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Calibration {
+    /// Exponent of the gamma curve (1.0 is linear, ~2.2 matches the perceived brightness of LEDs).
+    pub gamma: f32,
+    /// Gain for the red, green and blue channels.
+    pub gain: [f32; 3],
+    /// Color actually sent when pure white is requested (white balance).
+    pub white_point: Color,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Calibration {
+            gamma: 1.0,
+            gain: [1.0, 1.0, 1.0],
+            white_point: Color::WHITE,
+        }
+    }
+}
+
+impl Calibration {
+    /// Return the color that has to be sent to the device to display `color`.
+    pub fn apply(&self, color: Color) -> Color {
+        let white = [
+            self.white_point.red,
+            self.white_point.green,
+            self.white_point.blue,
+        ];
+        let correct = |i: usize, value: u8| {
+            let linear = (value as f32 / 255.0).powf(self.gamma.max(0.0));
+            let corrected = linear * self.gain[i] * (white[i] as f32 / 255.0);
+            (corrected.clamp(0.0, 1.0) * 255.0).round() as u8
+        };
+        Color::rgb(
+            correct(0, color.red),
+            correct(1, color.green),
+            correct(2, color.blue),
+        )
+    }
+
+    /// Load a calibration profile from a JSON file.
+    #[cfg(feature = "serde")]
+    pub fn load(path: impl AsRef<std::path::Path>) -> QKResult<Self> {
+        let file = std::fs::File::open(path)?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .map_err(|e| QKError::QKParseError(e.to_string()))
+    }
+
+    /// Save this calibration profile to a JSON file.
+    #[cfg(feature = "serde")]
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> QKResult<()> {
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(file, self).map_err(|e| QKError::QKParseError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests_calibration {
+    use super::*;
+
+    #[test]
+    fn it_should_not_change_colors_by_default() {
+        let calibration = Calibration::default();
+        for color in [Color::BLACK, Color::WHITE, Color::rgb(12, 128, 250)] {
+            assert_eq!(calibration.apply(color), color);
+        }
+    }
+
+    #[test]
+    fn it_should_apply_gamma() {
+        let calibration = Calibration {
+            gamma: 2.0,
+            ..Default::default()
+        };
+        assert_eq!(
+            calibration.apply(Color::rgb(0, 128, 255)),
+            Color::rgb(0, 64, 255)
+        );
+    }
+
+    #[test]
+    fn it_should_apply_gain_and_white_point() {
+        let calibration = Calibration {
+            gain: [1.0, 0.5, 2.0],
+            white_point: Color::rgb(255, 255, 51),
+            ..Default::default()
+        };
+        assert_eq!(calibration.apply(Color::WHITE), Color::rgb(255, 128, 102));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn it_should_persist_profiles() {
+        let path = std::env::temp_dir().join(format!("qk-calibration-{}.json", std::process::id()));
+        let calibration = Calibration {
+            gamma: 2.2,
+            gain: [1.0, 0.9, 0.8],
+            white_point: Color::rgb(255, 240, 220),
+        };
+        calibration.save(&path).unwrap();
+        let loaded = Calibration::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, calibration);
+    }
+}
@@ -0,0 +1,555 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::QKError;
+
+/// An RGB color, as displayed by the LED ring of the wheel.
+///
+/// Colors can be built from their components, from HSV/HSL coordinates or parsed from
+/// strings such as `"#ff8800"`, `"#f80"`, `"rgb(255, 136, 0)"`, `"hsv(32, 100%, 100%)"`,
+/// `"hsl(32, 100%, 50%)"` or any CSS named color (`"darkorange"`).
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Hash)]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Color {
+    // Named after the CSS colors they are equal to (CSS `green` is only half bright)
+    pub const BLACK: Color = Color::rgb(0, 0, 0);
+    pub const WHITE: Color = Color::rgb(255, 255, 255);
+    pub const RED: Color = Color::rgb(255, 0, 0);
+    pub const LIME: Color = Color::rgb(0, 255, 0);
+    pub const BLUE: Color = Color::rgb(0, 0, 255);
+    pub const YELLOW: Color = Color::rgb(255, 255, 0);
+    pub const CYAN: Color = Color::rgb(0, 255, 255);
+    pub const MAGENTA: Color = Color::rgb(255, 0, 255);
+
+    /// Build a color from its red, green and blue components.
+    pub const fn rgb(red: u8, green: u8, blue: u8) -> Self {
+        Color { red, green, blue }
+    }
+
+    /// Build a color from HSV coordinates (hue in degrees, saturation and value in 0.0-1.0).
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> Self {
+        let s = saturation.clamp(0.0, 1.0);
+        let v = value.clamp(0.0, 1.0);
+        let c = v * s;
+        let (r, g, b) = hue_to_rgb(hue, c);
+        let m = v - c;
+        Color::from_unit(r + m, g + m, b + m)
+    }
+
+    /// Build a color from HSL coordinates (hue in degrees, saturation and lightness in 0.0-1.0).
+    pub fn from_hsl(hue: f32, saturation: f32, lightness: f32) -> Self {
+        let s = saturation.clamp(0.0, 1.0);
+        let l = lightness.clamp(0.0, 1.0);
+        let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+        let (r, g, b) = hue_to_rgb(hue, c);
+        let m = l - c / 2.0;
+        Color::from_unit(r + m, g + m, b + m)
+    }
+
+    /// Return the HSV coordinates of this color (hue in degrees, saturation and value in 0.0-1.0).
+    pub fn to_hsv(&self) -> (f32, f32, f32) {
+        let (r, g, b) = self.to_unit();
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let delta = max - min;
+        let saturation = if max > 0.0 { delta / max } else { 0.0 };
+        (rgb_to_hue(r, g, b, max, delta), saturation, max)
+    }
+
+    /// Return the HSL coordinates of this color (hue in degrees, saturation and lightness in 0.0-1.0).
+    pub fn to_hsl(&self) -> (f32, f32, f32) {
+        let (r, g, b) = self.to_unit();
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let delta = max - min;
+        let lightness = (max + min) / 2.0;
+        let saturation = if delta > 0.0 {
+            delta / (1.0 - (2.0 * lightness - 1.0).abs())
+        } else {
+            0.0
+        };
+        (rgb_to_hue(r, g, b, max, delta), saturation, lightness)
+    }
+
+    /// Look up a CSS named color (case insensitive).
+    pub fn named(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        NAMED_COLORS
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|&(_, hex)| Color::rgb((hex >> 16) as u8, (hex >> 8) as u8, hex as u8))
+    }
+
+    /// Return the `#rrggbb` representation of this color.
+    pub fn to_hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.red, self.green, self.blue)
+    }
+
+    /// Linear interpolation in RGB space (`t` = 0.0 returns `self`, `t` = 1.0 returns `other`).
+    pub fn lerp(self, other: Color, t: f32) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+        Color::rgb(
+            mix(self.red, other.red),
+            mix(self.green, other.green),
+            mix(self.blue, other.blue),
+        )
+    }
+
+    /// Interpolation in HSV space, following the shortest path around the hue circle.
+    ///
+    /// Better suited than `lerp` for animations, as intermediate colors keep their
+    /// saturation instead of fading through gray.
+    pub fn lerp_hsv(self, other: Color, t: f32) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        let (h1, s1, v1) = self.to_hsv();
+        let (h2, s2, v2) = other.to_hsv();
+        // The hue of a gray is meaningless, borrow the one of the other end.
+        let h1 = if s1 == 0.0 { h2 } else { h1 };
+        let h2 = if s2 == 0.0 { h1 } else { h2 };
+        let mut dh = h2 - h1;
+        if dh > 180.0 {
+            dh -= 360.0;
+        } else if dh < -180.0 {
+            dh += 360.0;
+        }
+        Color::from_hsv(h1 + dh * t, s1 + (s2 - s1) * t, v1 + (v2 - v1) * t)
+    }
+
+    /// Return `steps` colors evenly spaced from `self` to `other` (both included).
+    pub fn gradient(self, other: Color, steps: usize) -> Vec<Color> {
+        match steps {
+            0 => Vec::new(),
+            1 => vec![self],
+            _ => (0..steps)
+                .map(|i| self.lerp(other, i as f32 / (steps - 1) as f32))
+                .collect(),
+        }
+    }
+
+    fn from_unit(r: f32, g: f32, b: f32) -> Self {
+        let conv = |x: f32| (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+        Color::rgb(conv(r), conv(g), conv(b))
+    }
+
+    fn to_unit(self) -> (f32, f32, f32) {
+        (
+            self.red as f32 / 255.0,
+            self.green as f32 / 255.0,
+            self.blue as f32 / 255.0,
+        )
+    }
+}
+
+/// Return the (r, g, b) contribution of a hue for a given chroma.
+fn hue_to_rgb(hue: f32, chroma: f32) -> (f32, f32, f32) {
+    let h = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+    match h as u8 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    }
+}
+
+fn rgb_to_hue(r: f32, g: f32, b: f32, max: f32, delta: f32) -> f32 {
+    if delta == 0.0 {
+        0.0
+    } else if max == r {
+        (60.0 * ((g - b) / delta)).rem_euclid(360.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    }
+}
+
+impl From<(u8, u8, u8)> for Color {
+    fn from((red, green, blue): (u8, u8, u8)) -> Self {
+        Color::rgb(red, green, blue)
+    }
+}
+
+impl From<[u8; 3]> for Color {
+    fn from([red, green, blue]: [u8; 3]) -> Self {
+        Color::rgb(red, green, blue)
+    }
+}
+
+impl From<Color> for (u8, u8, u8) {
+    fn from(color: Color) -> Self {
+        (color.red, color.green, color.blue)
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl FromStr for Color {
+    type Err = QKError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || QKError::QKInvalidColor(s.to_string());
+        let input = s.trim().to_ascii_lowercase();
+
+        if let Some(hex) = input.strip_prefix('#') {
+            return parse_hex(hex).ok_or_else(invalid);
+        }
+
+        if let Some((func, args)) = input
+            .strip_suffix(')')
+            .and_then(|rest| rest.split_once('('))
+        {
+            let args = args
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|a| !a.is_empty())
+                .collect::<Vec<&str>>();
+            if args.len() != 3 {
+                return Err(invalid());
+            }
+            return match func.trim() {
+                "rgb" => {
+                    let channel = |a: &str| parse_channel(a).ok_or_else(invalid);
+                    Ok(Color::rgb(
+                        channel(args[0])?,
+                        channel(args[1])?,
+                        channel(args[2])?,
+                    ))
+                }
+                "hsv" | "hsl" => {
+                    let hue = parse_hue(args[0]).ok_or_else(invalid)?;
+                    let s = parse_fraction(args[1]).ok_or_else(invalid)?;
+                    let vl = parse_fraction(args[2]).ok_or_else(invalid)?;
+                    Ok(if func.trim() == "hsv" {
+                        Color::from_hsv(hue, s, vl)
+                    } else {
+                        Color::from_hsl(hue, s, vl)
+                    })
+                }
+                _ => Err(invalid()),
+            };
+        }
+
+        Color::named(&input).ok_or_else(invalid)
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<Color> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).ok();
+    let byte = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    match hex.len() {
+        3 => Some(Color::rgb(digit(0)? * 17, digit(1)? * 17, digit(2)? * 17)),
+        6 => Some(Color::rgb(byte(0)?, byte(2)?, byte(4)?)),
+        _ => None,
+    }
+}
+
+/// Parse an RGB channel, either as an integer (0-255) or as a percentage.
+fn parse_channel(s: &str) -> Option<u8> {
+    match s.strip_suffix('%') {
+        Some(p) => {
+            let p = p.parse::<f32>().ok()?;
+            (0.0..=100.0)
+                .contains(&p)
+                .then(|| (p * 255.0 / 100.0).round() as u8)
+        }
+        None => s.parse::<u8>().ok(),
+    }
+}
+
+fn parse_hue(s: &str) -> Option<f32> {
+    s.strip_suffix("deg").unwrap_or(s).parse::<f32>().ok()
+}
+
+/// Parse a saturation/value/lightness, either as a percentage or as a fraction.
+fn parse_fraction(s: &str) -> Option<f32> {
+    let value = match s.strip_suffix('%') {
+        Some(p) => p.parse::<f32>().ok()? / 100.0,
+        None => s.parse::<f32>().ok()?,
+    };
+    (0.0..=1.0).contains(&value).then_some(value)
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Color {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Color {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// CSS named colors (https://www.w3.org/TR/css-color-4/#named-colors)
+const NAMED_COLORS: &[(&str, u32)] = &[
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
+#[cfg(test)]
+mod tests_color {
+    use super::*;
+
+    #[test]
+    fn it_should_parse_hex() {
+        assert_eq!("#ff8800".parse::<Color>().unwrap(), Color::rgb(255, 136, 0));
+        assert_eq!("#F80".parse::<Color>().unwrap(), Color::rgb(255, 136, 0));
+        assert!("#ff880".parse::<Color>().is_err());
+        assert!("#gg8800".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn it_should_parse_rgb() {
+        assert_eq!(
+            "rgb(255, 136, 0)".parse::<Color>().unwrap(),
+            Color::rgb(255, 136, 0)
+        );
+        assert_eq!(
+            "rgb(100% 0% 50%)".parse::<Color>().unwrap(),
+            Color::rgb(255, 0, 128)
+        );
+        assert!("rgb(256, 0, 0)".parse::<Color>().is_err());
+        assert!("rgb(1, 2)".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn it_should_parse_hsv_and_hsl() {
+        assert_eq!(
+            "hsv(120, 100%, 100%)".parse::<Color>().unwrap(),
+            Color::LIME
+        );
+        assert_eq!("hsl(240, 1, 0.5)".parse::<Color>().unwrap(), Color::BLUE);
+        assert_eq!(
+            "hsl(0deg, 0%, 100%)".parse::<Color>().unwrap(),
+            Color::WHITE
+        );
+        assert!("hsv(0, 200%, 100%)".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn it_should_parse_named_colors() {
+        assert_eq!(
+            "DarkOrange".parse::<Color>().unwrap(),
+            Color::rgb(255, 140, 0)
+        );
+        assert_eq!(" white ".parse::<Color>().unwrap(), Color::WHITE);
+        assert!("notacolor".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn it_should_name_constants_like_css() {
+        let constants = [
+            ("black", Color::BLACK),
+            ("white", Color::WHITE),
+            ("red", Color::RED),
+            ("lime", Color::LIME),
+            ("blue", Color::BLUE),
+            ("yellow", Color::YELLOW),
+            ("cyan", Color::CYAN),
+            ("magenta", Color::MAGENTA),
+        ];
+        for (name, color) in constants {
+            assert_eq!(name.parse::<Color>().unwrap(), color, "{}", name);
+        }
+    }
+
+    #[test]
+    fn it_should_display_as_hex() {
+        assert_eq!(Color::rgb(255, 136, 0).to_string(), "#ff8800");
+    }
+
+    #[test]
+    fn it_should_roundtrip_hsv() {
+        for color in [
+            Color::rgb(255, 136, 0),
+            Color::rgb(12, 200, 99),
+            Color::WHITE,
+            Color::BLACK,
+        ] {
+            let (h, s, v) = color.to_hsv();
+            assert_eq!(Color::from_hsv(h, s, v), color);
+            let (h, s, l) = color.to_hsl();
+            assert_eq!(Color::from_hsl(h, s, l), color);
+        }
+    }
+
+    #[test]
+    fn it_should_interpolate() {
+        assert_eq!(
+            Color::BLACK.lerp(Color::WHITE, 0.5),
+            Color::rgb(128, 128, 128)
+        );
+        assert_eq!(Color::RED.lerp(Color::BLUE, 0.0), Color::RED);
+        assert_eq!(Color::RED.lerp(Color::BLUE, 1.0), Color::BLUE);
+        assert_eq!(Color::RED.lerp_hsv(Color::BLUE, 0.5), Color::MAGENTA);
+        assert_eq!(
+            Color::BLACK.gradient(Color::WHITE, 3)[1],
+            Color::rgb(128, 128, 128)
+        );
+    }
+}
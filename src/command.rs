@@ -0,0 +1,274 @@
+use crate::color::Color;
+use crate::error::QKError;
+use crate::msgs::*;
+use crate::QKResult;
+
+/// Longest text an overlay can show, in bytes
+const MAX_OVERLAY_TEXT: usize = 32;
+
+/// An operation that can be sent to the device (see `QKDevice::send`)
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Command {
+    /// Ask the device to report a class of events
+    Subscribe { class: EventClass },
+    /// Rotate the screen
+    SetScreenOrientation { orientation: ScreenOrientation },
+    /// Change the screen brightness
+    SetScreenBrightness { level: ScreenBrightness },
+    /// Change the wheel speed
+    SetWheelSpeed { speed: WheelSpeed },
+    /// Minutes the device stays awake after losing connection
+    SetSleepTimeout { minutes: u8 },
+    /// Change the color of the LED ring of the wheel
+    SetRingColor { color: Color },
+    /// Set the text label of a key (0-7)
+    SetKeyText { key: u8, text: String },
+    /// Show a text overlay for a number of seconds
+    ShowOverlayText { text: String, seconds: u8 },
+}
+
+impl Command {
+    /// Check that the command can be sent: keys are numbered 0-7 and overlay texts are at
+    /// most 32 bytes long (longer key labels are cut at 8 characters).
+    pub fn validate(&self) -> QKResult<()> {
+        match self {
+            Command::SetKeyText { key, .. } if *key > 7 => Err(QKError::QKInvalidCommand(format!(
+                "key {} out of range (0-7)",
+                key
+            ))),
+            Command::ShowOverlayText { text, .. } if text.len() > MAX_OVERLAY_TEXT => {
+                Err(QKError::QKInvalidCommand(format!(
+                    "overlay text longer than {} bytes",
+                    MAX_OVERLAY_TEXT
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Encode the command as the sequence of frames to be written to the device.
+    ///
+    /// Fails with `QKError::QKInvalidCommand` if the command can't be sent (see `validate`).
+    pub fn encode(&self) -> QKResult<Vec<[u8; 32]>> {
+        self.validate()?;
+        Ok(match self {
+            Command::Subscribe { class } => vec![msg_subscribe(*class)],
+            Command::SetScreenOrientation { orientation } => vec![msg_rotate_screen(*orientation)],
+            Command::SetScreenBrightness { level } => vec![msg_set_screen_brightness(*level)],
+            Command::SetWheelSpeed { speed } => vec![msg_set_wheel_speed(*speed)],
+            Command::SetSleepTimeout { minutes } => vec![msg_set_sleep_timeout(*minutes)],
+            Command::SetRingColor { color } => {
+                vec![msg_set_wheel_color(color.red, color.green, color.blue)]
+            }
+            Command::SetKeyText { key, text } => vec![msg_set_key_text(*key, text)],
+            Command::ShowOverlayText { text, seconds } => msgs_show_overlay_text(*seconds, text),
+        })
+    }
+}
+
+/// One of the frames of a text overlay (see `Command::ShowOverlayText`)
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OverlayChunk {
+    /// Whether this is the first frame of the overlay
+    pub first: bool,
+    pub seconds: u8,
+    pub text: String,
+    pub has_more: bool,
+}
+
+/// A frame sent to the device, as understood by `decode_command`
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DecodedFrame {
+    /// A frame holding a whole command
+    Command(Command),
+    /// One of the frames of a text overlay
+    OverlayChunk(OverlayChunk),
+}
+
+impl DecodedFrame {
+    /// Encode the frame(s) back.
+    pub fn encode(&self) -> QKResult<Vec<[u8; 32]>> {
+        match self {
+            DecodedFrame::Command(command) => command.encode(),
+            DecodedFrame::OverlayChunk(chunk) => Ok(vec![submsg_overlay_chunk(
+                !chunk.first,
+                chunk.seconds,
+                &chunk.text,
+                chunk.has_more,
+            )]),
+        }
+    }
+}
+
+/// Decode a frame sent to the device (the inverse of the `msg_*` functions).
+///
+/// Returns `None` for frames that are not understood, including frames of known commands
+/// carrying unexpected extra bytes.
+pub fn decode_command(frame: &[u8; 32]) -> Option<DecodedFrame> {
+    let command = |command| Some(DecodedFrame::Command(command));
+    let decoded = match frame[..6] {
+        [0x02, 0xb0, 0x04, ..] => command(Command::Subscribe {
+            class: EventClass::Keys,
+        }),
+        [0x02, 0xb4, 0x10, ..] => command(Command::Subscribe {
+            class: EventClass::Battery,
+        }),
+        [0x02, 0xb1, 0x00, key, ..] if key > 0 => command(Command::SetKeyText {
+            key: key - 1,
+            text: decode_text(frame),
+        }),
+        [0x02, 0xb1, 0x0a, 0x01, level, _] => command(Command::SetScreenBrightness {
+            level: level.try_into().ok()?,
+        }),
+        [0x02, 0xb1, 0x05 | 0x06, seconds, ..] => Some(DecodedFrame::OverlayChunk(OverlayChunk {
+            first: frame[2] == 0x05,
+            seconds,
+            text: decode_text(frame),
+            has_more: frame[6] != 0,
+        })),
+        [0x02, 0xb1, orientation, ..] => command(Command::SetScreenOrientation {
+            orientation: orientation.try_into().ok()?,
+        }),
+        [0x02, 0xb4, 0x04, 0x01, 0x01, speed] => command(Command::SetWheelSpeed {
+            speed: speed.try_into().ok()?,
+        }),
+        [0x02, 0xb4, 0x08, 0x01, minutes, _] => command(Command::SetSleepTimeout { minutes }),
+        [0x02, 0xb4, 0x01, 0x01, 0x00, 0x00] => command(Command::SetRingColor {
+            color: Color::rgb(frame[6], frame[7], frame[8]),
+        }),
+        _ => None,
+    }?;
+    // Anything not covered by the decoded fields means it is not the command we think it is
+    (decoded.encode().ok()? == [*frame]).then_some(decoded)
+}
+
+/// Decode the UTF-16 text of a key label or overlay frame.
+fn decode_text(frame: &[u8; 32]) -> String {
+    let units = frame[16..16 + (frame[5] as usize).min(16)]
+        .chunks(2)
+        .map(|c| u16::from_le_bytes([c[0], c.get(1).copied().unwrap_or(0)]))
+        .take_while(|&u| u != 0)
+        .collect::<Vec<u16>>();
+    String::from_utf16_lossy(&units)
+}
+
+#[cfg(test)]
+mod tests_command {
+    use super::*;
+
+    #[test]
+    fn it_should_encode_single_frame_commands() {
+        assert_eq!(
+            Command::SetScreenOrientation {
+                orientation: ScreenOrientation::Rotate90
+            }
+            .encode()
+            .unwrap(),
+            vec![msg_rotate_screen(ScreenOrientation::Rotate90)]
+        );
+        assert_eq!(
+            Command::SetRingColor {
+                color: Color::rgb(1, 2, 3)
+            }
+            .encode()
+            .unwrap(),
+            vec![msg_set_wheel_color(1, 2, 3)]
+        );
+        assert_eq!(
+            Command::SetKeyText {
+                key: 3,
+                text: "baazquux".to_string()
+            }
+            .encode()
+            .unwrap(),
+            vec![msg_set_key_text(3, "baazquux")]
+        );
+    }
+
+    #[test]
+    fn it_should_encode_overlays_as_several_frames() {
+        let frames = Command::ShowOverlayText {
+            text: "Disco, disco!".to_string(),
+            seconds: 2,
+        }
+        .encode()
+        .unwrap();
+        assert_eq!(frames, msgs_show_overlay_text(2, "Disco, disco!"));
+        assert_eq!(frames.len(), 2);
+    }
+
+    #[test]
+    fn it_should_reject_invalid_commands() {
+        let invalid = [
+            Command::SetKeyText {
+                key: 8,
+                text: "a".to_string(),
+            },
+            Command::SetKeyText {
+                key: 255,
+                text: "a".to_string(),
+            },
+            Command::ShowOverlayText {
+                text: "x".repeat(33),
+                seconds: 1,
+            },
+        ];
+        for command in invalid {
+            assert!(matches!(
+                command.encode(),
+                Err(QKError::QKInvalidCommand(_))
+            ));
+        }
+        assert_eq!(decode_command(&pad_zeroes([0x02, 0xb1, 0x00, 0x09])), None);
+    }
+
+    #[test]
+    fn it_should_decode_commands() {
+        let commands = [
+            Command::Subscribe {
+                class: EventClass::Battery,
+            },
+            Command::SetScreenBrightness {
+                level: ScreenBrightness::Off,
+            },
+            Command::SetScreenOrientation {
+                orientation: ScreenOrientation::Rotate270,
+            },
+            Command::SetWheelSpeed {
+                speed: WheelSpeed::Slowest,
+            },
+            Command::SetSleepTimeout { minutes: 30 },
+            Command::SetKeyText {
+                key: 7,
+                text: "ok".to_string(),
+            },
+        ];
+        for command in commands {
+            let frame = command.encode().unwrap()[0];
+            assert_eq!(decode_command(&frame), Some(DecodedFrame::Command(command)));
+        }
+    }
+
+    #[test]
+    fn it_should_not_decode_unknown_frames() {
+        assert_eq!(decode_command(&[0u8; 32]), None);
+        assert_eq!(decode_command(&pad_zeroes([0x02, 0xb1, 0x09])), None);
+        let mut frame = msg_set_wheel_speed(WheelSpeed::Normal);
+        frame[10] = 1;
+        assert_eq!(decode_command(&frame), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn it_should_serialize() {
+        let command = Command::SetRingColor {
+            color: Color::rgb(255, 136, 0),
+        };
+        let json = serde_json::to_string(&command).unwrap();
+        assert_eq!(json, r##"{"SetRingColor":{"color":"#ff8800"}}"##);
+        assert_eq!(serde_json::from_str::<Command>(&json).unwrap(), command);
+    }
+}
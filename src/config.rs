@@ -0,0 +1,170 @@
+use crate::color::Color;
+use crate::command::Command;
+use crate::error::QKError;
+use crate::msgs::{ScreenBrightness, ScreenOrientation, WheelSpeed};
+use crate::state::DeviceState;
+
+/// The settings of a device, to be applied all at once with `QKDevice::apply`.
+///
+/// Fields left to `None` are not touched.
+#[derive(Debug, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct DeviceConfig {
+    pub orientation: Option<ScreenOrientation>,
+    pub brightness: Option<ScreenBrightness>,
+    pub wheel_speed: Option<WheelSpeed>,
+    pub sleep_timeout: Option<u8>,
+    pub ring_color: Option<Color>,
+    pub key_texts: [Option<String>; 8],
+}
+
+/// One of the settings of a `DeviceConfig`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ConfigField {
+    Orientation,
+    Brightness,
+    WheelSpeed,
+    SleepTimeout,
+    RingColor,
+    /// The label of a key (0-7)
+    KeyText(u8),
+}
+
+/// The outcome of `QKDevice::apply`
+#[derive(Debug, Default)]
+pub struct ApplyReport {
+    /// Settings sent to the device
+    pub applied: Vec<ConfigField>,
+    /// Settings that were already in place
+    pub skipped: Vec<ConfigField>,
+    /// Settings that couldn't be sent, with the reason
+    pub failed: Vec<(ConfigField, QKError)>,
+}
+
+impl ApplyReport {
+    /// Whether every setting is now in place.
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+impl DeviceConfig {
+    /// The commands setting each of the fields of the configuration.
+    pub fn commands(&self) -> Vec<(ConfigField, Command)> {
+        let mut commands = Vec::new();
+        if let Some(orientation) = self.orientation {
+            commands.push((
+                ConfigField::Orientation,
+                Command::SetScreenOrientation { orientation },
+            ));
+        }
+        if let Some(level) = self.brightness {
+            commands.push((
+                ConfigField::Brightness,
+                Command::SetScreenBrightness { level },
+            ));
+        }
+        if let Some(speed) = self.wheel_speed {
+            commands.push((ConfigField::WheelSpeed, Command::SetWheelSpeed { speed }));
+        }
+        if let Some(minutes) = self.sleep_timeout {
+            commands.push((
+                ConfigField::SleepTimeout,
+                Command::SetSleepTimeout { minutes },
+            ));
+        }
+        if let Some(color) = self.ring_color {
+            commands.push((ConfigField::RingColor, Command::SetRingColor { color }));
+        }
+        for (key, text) in self.key_texts.iter().enumerate() {
+            if let Some(text) = text {
+                commands.push((
+                    ConfigField::KeyText(key as u8),
+                    Command::SetKeyText {
+                        key: key as u8,
+                        text: text.clone(),
+                    },
+                ));
+            }
+        }
+        commands
+    }
+}
+
+/// Whether sending the command wouldn't change the given state.
+pub(crate) fn is_in_place(command: &Command, state: &DeviceState) -> bool {
+    match command {
+        Command::SetScreenOrientation { orientation } => state.orientation == Some(*orientation),
+        Command::SetScreenBrightness { level } => state.brightness == Some(*level),
+        Command::SetWheelSpeed { speed } => state.wheel_speed == Some(*speed),
+        Command::SetSleepTimeout { minutes } => state.sleep_timeout == Some(*minutes),
+        Command::SetRingColor { color } => state.ring_color == Some(*color),
+        Command::SetKeyText { key, text } => {
+            matches!(state.key_texts.get(*key as usize), Some(Some(t)) if t == text)
+        }
+        Command::Subscribe { .. } | Command::ShowOverlayText { .. } => false,
+    }
+}
+
+#[cfg(test)]
+mod tests_config {
+    use super::*;
+
+    #[test]
+    fn it_should_list_only_set_fields() {
+        let mut config = DeviceConfig {
+            wheel_speed: Some(WheelSpeed::Slower),
+            ..DeviceConfig::default()
+        };
+        config.key_texts[3] = Some("save".to_string());
+        let fields = config
+            .commands()
+            .into_iter()
+            .map(|(field, _)| field)
+            .collect::<Vec<ConfigField>>();
+        assert_eq!(
+            fields,
+            vec![ConfigField::WheelSpeed, ConfigField::KeyText(3)]
+        );
+    }
+
+    #[test]
+    fn it_should_compare_with_the_state() {
+        let mut state = DeviceState {
+            ring_color: Some(Color::RED),
+            ..DeviceState::default()
+        };
+        state.key_texts[0] = Some("undo".to_string());
+        assert!(is_in_place(
+            &Command::SetRingColor { color: Color::RED },
+            &state
+        ));
+        assert!(!is_in_place(
+            &Command::SetRingColor { color: Color::BLUE },
+            &state
+        ));
+        let label = |key, text: &str| Command::SetKeyText {
+            key,
+            text: text.to_string(),
+        };
+        assert!(is_in_place(&label(0, "undo"), &state));
+        assert!(!is_in_place(&label(0, "redo"), &state));
+        assert!(!is_in_place(&label(1, "undo"), &state));
+        let speed = Command::SetWheelSpeed {
+            speed: WheelSpeed::Normal,
+        };
+        assert!(!is_in_place(&speed, &state));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn it_should_deserialize_partial_configs() {
+        let config: DeviceConfig =
+            serde_json::from_str(r##"{"ring_color": "#ff0000", "sleep_timeout": 5}"##).unwrap();
+        assert_eq!(config.ring_color, Some(Color::RED));
+        assert_eq!(config.sleep_timeout, Some(5));
+        assert_eq!(config.orientation, None);
+    }
+}
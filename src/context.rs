@@ -0,0 +1,457 @@
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::layers::{LayerSwitcher, Profile};
+use crate::{QKDevice, QKResult};
+
+/// Something telling in which context the keypad is used, e.g. the class of the focused
+/// window.  What a context looks like is up to the provider: it is just matched against the
+/// patterns of a `ProfileSelector`.
+pub trait ContextProvider {
+    /// The current context (`None` if unknown).  Must not block.
+    fn current(&mut self) -> QKResult<Option<String>>;
+}
+
+/// Read the context from a file, e.g. written by a window manager hook.
+///
+/// The context is the content of the file without surrounding whitespace; a missing or
+/// empty file means an unknown context.
+pub struct FileContext {
+    path: PathBuf,
+}
+
+impl FileContext {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        FileContext {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl ContextProvider for FileContext {
+    fn current(&mut self) -> QKResult<Option<String>> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(s) => Ok(Some(s.trim().to_string()).filter(|s| !s.is_empty())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Time given to a client of `SocketContext` to send the context
+const CONTEXT_TIMEOUT: Duration = Duration::from_millis(100);
+/// Longest context accepted by `SocketContext`, in bytes
+const MAX_CONTEXT_LEN: usize = 4096;
+
+/// Listen on a Unix socket, replacing a stale one left by a process that is gone.
+///
+/// Fails if something else than a socket is at the path, or if a process is still
+/// listening on it.
+#[cfg(unix)]
+pub(crate) fn bind_socket(path: &Path) -> QKResult<std::os::unix::net::UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.file_type().is_socket() => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            )
+            .into());
+        }
+        Ok(_) if UnixStream::connect(path).is_ok() => {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is in use by another process", path.display()),
+            )
+            .into());
+        }
+        Ok(_) => std::fs::remove_file(path)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    Ok(UnixListener::bind(path)?)
+}
+
+/// Receive the context through a Unix socket: each connection sends the new context as a
+/// line, e.g. `echo firefox | socat - UNIX-CONNECT:/run/user/1000/qk-context`.
+///
+/// Connections are handled by a background thread, so `current` never waits for clients.
+#[cfg(unix)]
+pub struct SocketContext {
+    path: PathBuf,
+    shared: Arc<Mutex<Received>>,
+}
+
+/// What the thread of a `SocketContext` received so far
+#[cfg(unix)]
+#[derive(Default)]
+struct Received {
+    context: Option<String>,
+    error: Option<io::Error>,
+    closed: bool,
+}
+
+#[cfg(unix)]
+impl SocketContext {
+    /// Listen on the given path (replacing a stale socket, see `bind_socket`).
+    pub fn bind(path: impl AsRef<Path>) -> QKResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let listener = bind_socket(&path)?;
+        let shared = Arc::new(Mutex::new(Received::default()));
+        {
+            let shared = Arc::clone(&shared);
+            std::thread::spawn(move || listen(listener, shared));
+        }
+        Ok(SocketContext { path, shared })
+    }
+}
+
+/// Accept the clients one at a time, until the `SocketContext` is dropped.
+#[cfg(unix)]
+fn listen(listener: std::os::unix::net::UnixListener, shared: Arc<Mutex<Received>>) {
+    let lock = || shared.lock().unwrap_or_else(|e| e.into_inner());
+    for stream in listener.incoming() {
+        if lock().closed {
+            return;
+        }
+        match stream {
+            Ok(stream) => {
+                if let Some(context) = receive(stream) {
+                    lock().context = Some(context);
+                }
+            }
+            Err(e) => {
+                lock().error = Some(e);
+                return;
+            }
+        }
+    }
+}
+
+/// The last non-empty line sent by a client.
+///
+/// A client keeping the connection open only delays the update, up to `CONTEXT_TIMEOUT`.
+#[cfg(unix)]
+fn receive(mut stream: std::os::unix::net::UnixStream) -> Option<String> {
+    let deadline = Instant::now() + CONTEXT_TIMEOUT;
+    let mut received = Vec::new();
+    let mut buf = [0u8; 256];
+    while received.len() < MAX_CONTEXT_LEN {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() || stream.set_read_timeout(Some(remaining)).is_err() {
+            break;
+        }
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(len) => received.extend_from_slice(&buf[..len]),
+        }
+    }
+    let received = String::from_utf8_lossy(&received);
+    received
+        .lines()
+        .map(str::trim)
+        .rfind(|l| !l.is_empty())
+        .map(str::to_string)
+}
+
+#[cfg(unix)]
+impl ContextProvider for SocketContext {
+    fn current(&mut self) -> QKResult<Option<String>> {
+        let mut received = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+        match received.error.take() {
+            Some(e) => Err(e.into()),
+            None => Ok(received.context.clone()),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for SocketContext {
+    fn drop(&mut self) {
+        self.shared.lock().unwrap_or_else(|e| e.into_inner()).closed = true;
+        // Wake the thread up so that it sees it is closed
+        let _ = std::os::unix::net::UnixStream::connect(&self.path);
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Pick a profile depending on the context (see `ContextProvider`).
+///
+/// Rules are tried in order, their patterns may use `*` (any characters) and `?` (any
+/// character).  When no rule matches, the fallback profile is used, if any.
+///
+/// ```no_run
+/// # use xencelabs_quick_keys::*;
+/// # fn main() -> QKResult<()> {
+/// let dev = QKDevice::open(hidapi::HidApi::new()?, ConnectionMode::Auto)?;
+/// # let (gimp, default) = (Profile::new(vec![]), Profile::new(vec![]));
+/// let mut selector = ProfileSelector::new()
+///     .rule("gimp*", gimp)
+///     .fallback(default);
+/// let mut context = FileContext::new("/tmp/focused-window");
+/// let mut layers = LayerSwitcher::new(selector.fallback_profile().unwrap().clone());
+/// layers.activate(&dev)?;
+/// loop {
+///     if let Some(event) = dev.read_timeout(100)? {
+///         layers.handle(&dev, &event)?;
+///     }
+///     selector.update(&mut context, &dev, &mut layers)?;
+/// }
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ProfileSelector {
+    rules: Vec<(String, Profile)>,
+    fallback: Option<Profile>,
+    /// Rule (or `rules.len()` for the fallback) whose profile is active
+    active: Option<usize>,
+}
+
+impl ProfileSelector {
+    pub fn new() -> Self {
+        ProfileSelector::default()
+    }
+
+    /// Use the profile when the context matches the pattern.
+    pub fn rule(mut self, pattern: &str, profile: Profile) -> Self {
+        self.rules.push((pattern.to_string(), profile));
+        self
+    }
+
+    /// Use the profile when no rule matches.
+    pub fn fallback(mut self, profile: Profile) -> Self {
+        self.fallback = Some(profile);
+        self
+    }
+
+    pub fn fallback_profile(&self) -> Option<&Profile> {
+        self.fallback.as_ref()
+    }
+
+    /// Index of the rule matching the context (`rules.len()` for the fallback).
+    fn find(&self, context: Option<&str>) -> Option<usize> {
+        context
+            .and_then(|c| self.rules.iter().position(|(p, _)| glob_match(p, c)))
+            .or(self.fallback.as_ref().map(|_| self.rules.len()))
+    }
+
+    /// The profile for the given context.
+    pub fn select(&self, context: Option<&str>) -> Option<&Profile> {
+        let index = self.find(context)?;
+        self.rules
+            .get(index)
+            .map(|(_, p)| p)
+            .or(self.fallback.as_ref())
+    }
+
+    /// Switch the layers to the profile for the current context, if it isn't already active.
+    ///
+    /// Returns whether the profile changed.  When nothing matches and there is no fallback,
+    /// the active profile is kept.
+    pub fn update(
+        &mut self,
+        provider: &mut dyn ContextProvider,
+        dev: &QKDevice,
+        layers: &mut LayerSwitcher,
+    ) -> QKResult<bool> {
+        let context = provider.current()?;
+        let Some(index) = self.find(context.as_deref()) else {
+            return Ok(false);
+        };
+        if self.active == Some(index) {
+            return Ok(false);
+        }
+        let profile = self.select(context.as_deref()).cloned();
+        if let Some(profile) = profile {
+            layers.set_profile(dev, profile)?;
+        }
+        self.active = Some(index);
+        Ok(true)
+    }
+}
+
+/// Whether the text matches the pattern (`*` for any characters, `?` for any character).
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let text = text.chars().collect::<Vec<char>>();
+    let (mut p, mut t) = (0, 0);
+    // Last `*` seen, and the position in the text it was tried at
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                // Let the last `*` swallow one more character
+                Some((sp, st)) => {
+                    p = sp + 1;
+                    t = st + 1;
+                    star = Some((sp, st + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests_context {
+    use super::*;
+    use crate::color::Color;
+    use crate::layers::Layer;
+    use crate::record::testing::{replay, temp_path};
+
+    struct Fixed(Option<String>);
+
+    impl ContextProvider for Fixed {
+        fn current(&mut self) -> QKResult<Option<String>> {
+            Ok(self.0.clone())
+        }
+    }
+
+    fn profile(color: Color) -> Profile {
+        Profile::new(vec![Layer {
+            ring_color: color,
+            ..Layer::default()
+        }])
+    }
+
+    #[test]
+    fn it_should_match_globs() {
+        assert!(glob_match("firefox", "firefox"));
+        assert!(!glob_match("firefox", "firefox-esr"));
+        assert!(glob_match("firefox*", "firefox-esr"));
+        assert!(glob_match("*gimp*", "org.gimp.GIMP-gimp-2.10"));
+        assert!(glob_match("g?mp", "gimp"));
+        assert!(!glob_match("g?mp", "gmp"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXXbYYbc"));
+        assert!(!glob_match("a*b*c", "aXXbYY"));
+    }
+
+    #[test]
+    fn it_should_select_profiles() {
+        let selector = ProfileSelector::new()
+            .rule("gimp*", profile(Color::RED))
+            .rule("*", profile(Color::LIME));
+        let color = |c| selector.select(c).map(|p| p.layers[0].ring_color);
+        assert_eq!(color(Some("gimp-2.10")), Some(Color::RED));
+        assert_eq!(color(Some("firefox")), Some(Color::LIME));
+        assert_eq!(color(None), None);
+        let selector = selector.fallback(profile(Color::BLUE));
+        assert_eq!(
+            selector.select(None).map(|p| p.layers[0].ring_color),
+            Some(Color::BLUE)
+        );
+    }
+
+    #[test]
+    fn it_should_switch_profiles_when_the_context_changes() {
+        let (dev, _) = replay(&[]);
+        let mut selector = ProfileSelector::new()
+            .rule("gimp", profile(Color::RED))
+            .fallback(profile(Color::BLUE));
+        let mut layers = LayerSwitcher::new(profile(Color::BLACK));
+        let mut context = Fixed(Some("gimp".to_string()));
+        assert!(selector.update(&mut context, &dev, &mut layers).unwrap());
+        assert_eq!(dev.ring_color(), Some(Color::RED));
+        assert!(!selector.update(&mut context, &dev, &mut layers).unwrap());
+        context.0 = Some("firefox".to_string());
+        assert!(selector.update(&mut context, &dev, &mut layers).unwrap());
+        assert_eq!(dev.ring_color(), Some(Color::BLUE));
+    }
+
+    #[test]
+    fn it_should_read_the_context_from_a_file() {
+        let path = temp_path("context-file");
+        let mut context = FileContext::new(&path);
+        assert_eq!(context.current().unwrap(), None);
+        std::fs::write(&path, "gimp\n").unwrap();
+        assert_eq!(context.current().unwrap().as_deref(), Some("gimp"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Send a context to a `SocketContext`, like a window manager hook would.
+    #[cfg(unix)]
+    fn send(path: &Path, text: &str) {
+        use std::io::Write;
+
+        let mut client = std::os::unix::net::UnixStream::connect(path).unwrap();
+        client.write_all(text.as_bytes()).unwrap();
+    }
+
+    /// The context once it is the expected one, waiting one second at most.
+    #[cfg(unix)]
+    fn wait_for(context: &mut SocketContext, expected: &str) -> Option<String> {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        loop {
+            let current = context.current().unwrap();
+            if current.as_deref() == Some(expected) || Instant::now() >= deadline {
+                return current;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_should_receive_the_context_from_a_socket() {
+        let path = temp_path("context-socket");
+        let mut context = SocketContext::bind(&path).unwrap();
+        assert_eq!(context.current().unwrap(), None);
+        send(&path, "firefox\n");
+        assert_eq!(
+            wait_for(&mut context, "firefox").as_deref(),
+            Some("firefox")
+        );
+        // The context stays until another one is sent
+        assert_eq!(context.current().unwrap().as_deref(), Some("firefox"));
+        send(&path, "gimp\n");
+        assert_eq!(wait_for(&mut context, "gimp").as_deref(), Some("gimp"));
+        drop(context);
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_should_not_wait_for_slow_clients() {
+        let path = temp_path("context-slow");
+        let mut context = SocketContext::bind(&path).unwrap();
+        // Connected, but not sending anything
+        let silent = std::os::unix::net::UnixStream::connect(&path).unwrap();
+        assert_eq!(context.current().unwrap(), None);
+        send(&path, "gimp\n");
+        assert_eq!(wait_for(&mut context, "gimp").as_deref(), Some("gimp"));
+        drop(silent);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_should_only_replace_stale_sockets() {
+        let path = temp_path("context-stale");
+        std::fs::write(&path, "precious").unwrap();
+        assert!(SocketContext::bind(&path).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "precious");
+        std::fs::remove_file(&path).unwrap();
+
+        let live = SocketContext::bind(&path).unwrap();
+        assert!(SocketContext::bind(&path).is_err());
+        assert!(path.exists());
+        drop(live);
+
+        // Left behind by a process that is gone
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(SocketContext::bind(&path).is_ok());
+    }
+}
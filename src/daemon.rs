@@ -0,0 +1,614 @@
+//! A daemon owning the device, so that several programs can drive it at once.
+//!
+//! Clients connect to a Unix socket and exchange JSON objects, one per line.  Each
+//! `DaemonRequest` gets exactly one `DaemonResponse`, e.g.:
+//!
+//! ```text
+//! > {"request": "send", "command": {"SetKeyText": {"key": 0, "text": "Copy"}}}
+//! < {"response": "ok"}
+//! > {"request": "state"}
+//! < {"response": "state", "state": {"orientation": null, ...}}
+//! ```
+//!
+//! After a `subscribe` request, the connection only receives the events of the device, as
+//! `{"response": "event", "event": ...}` lines.
+//!
+//! Requests are handled one at a time by the thread owning the device, in the order they
+//! arrive, so commands from different clients are never interleaved.
+//!
+//! At most 64 clients are served at once, and a request longer than 64 KiB closes the
+//! connection; both are answered with an `Error` response first.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::command::Command;
+use crate::config::{ConfigField, DeviceConfig};
+use crate::context::bind_socket;
+use crate::error::QKError;
+use crate::msgs::Event;
+use crate::state::DeviceState;
+use crate::{QKDevice, QKResult};
+
+/// Milliseconds the daemon waits for an event before handling the pending requests
+const POLL_MS: i32 = 20;
+/// Events a subscriber may lag behind before being disconnected
+const SUBSCRIBER_BACKLOG: usize = 64;
+/// A subscriber not reading its events for this long is disconnected
+const SUBSCRIBER_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Longest request accepted, in bytes: a client sending a longer one is disconnected
+const MAX_REQUEST_LEN: usize = 64 * 1024;
+/// Clients (subscribers included) served at once: further connections are refused
+const MAX_CLIENTS: usize = 64;
+
+/// A request sent to the daemon
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum DaemonRequest {
+    /// Send a command to the device (answered with `Ok`)
+    Send { command: Command },
+    /// Send the settings of the configuration that are not in place yet (answered with
+    /// `Applied`, see `QKDevice::apply`)
+    Apply { config: DeviceConfig },
+    /// Get what was last sent to the device (answered with `State`)
+    State,
+    /// Turn the connection into a stream of `Event` responses (answered with `Ok` first)
+    Subscribe,
+}
+
+/// A response of the daemon
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "snake_case")]
+pub enum DaemonResponse {
+    Ok,
+    Applied {
+        applied: Vec<ConfigField>,
+        skipped: Vec<ConfigField>,
+        failed: Vec<(ConfigField, String)>,
+    },
+    State {
+        state: DeviceState,
+    },
+    Event {
+        event: Event,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl DaemonResponse {
+    fn error(e: impl ToString) -> Self {
+        DaemonResponse::Error {
+            message: e.to_string(),
+        }
+    }
+}
+
+/// Default path of the daemon socket: `$XDG_RUNTIME_DIR/qkd.sock`, or in the temporary
+/// directory if that isn't set.
+pub fn default_socket_path() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("qkd.sock")
+}
+
+/// What the client threads hand over to the thread owning the device
+enum Incoming {
+    Request(Box<DaemonRequest>, Sender<DaemonResponse>),
+    Subscribe(UnixStream),
+}
+
+/// Serve a device through a Unix socket (see the module documentation for the protocol).
+///
+/// ```no_run
+/// # use xencelabs_quick_keys::*;
+/// # fn main() -> QKResult<()> {
+/// let dev = QKDevice::open(hidapi::HidApi::new()?, ConnectionMode::Auto)?;
+/// Daemon::bind(dev, default_socket_path())?.run()
+/// # }
+/// ```
+pub struct Daemon {
+    dev: QKDevice,
+    path: PathBuf,
+    listener: UnixListener,
+    sender: Sender<Incoming>,
+    incoming: Receiver<Incoming>,
+    subscribers: Vec<SyncSender<Event>>,
+    /// Threads serving the clients and the subscribers
+    clients: Vec<JoinHandle<()>>,
+}
+
+impl Daemon {
+    /// Listen on the given path, replacing a stale socket but failing if another daemon
+    /// is still listening on it.
+    pub fn bind(dev: QKDevice, path: impl AsRef<Path>) -> QKResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let listener = bind_socket(&path)?;
+        listener.set_nonblocking(true)?;
+        let (sender, incoming) = mpsc::channel();
+        Ok(Daemon {
+            dev,
+            path,
+            listener,
+            sender,
+            incoming,
+            subscribers: Vec::new(),
+            clients: Vec::new(),
+        })
+    }
+
+    /// The served device.
+    pub fn device(&self) -> &QKDevice {
+        &self.dev
+    }
+
+    /// Serve the clients until the device is disconnected.
+    pub fn run(&mut self) -> QKResult<()> {
+        loop {
+            match self.poll(POLL_MS) {
+                Ok(()) => {}
+                Err(QKError::QKDisconnected) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Accept the new clients, handle the pending requests and wait up to `timeout`
+    /// milliseconds for an event to forward to the subscribers.
+    ///
+    /// When the device is gone, subscribers get an `Event::Disconnected` and
+    /// `QKError::QKDisconnected` is returned.
+    pub fn poll(&mut self, timeout: i32) -> QKResult<()> {
+        self.accept()?;
+        while let Ok(incoming) = self.incoming.try_recv() {
+            match incoming {
+                Incoming::Request(request, reply) => {
+                    let _ = reply.send(self.handle(*request));
+                }
+                Incoming::Subscribe(stream) => self.add_subscriber(stream),
+            }
+        }
+        match self.dev.read_timeout(timeout) {
+            Ok(Some(event)) => self.broadcast(event),
+            Ok(None) => {}
+            Err(QKError::QKDisconnected) => {
+                self.broadcast(Event::Disconnected);
+                return Err(QKError::QKDisconnected);
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    fn accept(&mut self) -> QKResult<()> {
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+            stream.set_nonblocking(false)?;
+            self.clients.retain(|client| !client.is_finished());
+            if self.clients.len() >= MAX_CLIENTS {
+                let mut stream = stream;
+                let _ = write_line(&mut stream, &DaemonResponse::error("too many clients"));
+                continue;
+            }
+            let sender = self.sender.clone();
+            self.clients
+                .push(std::thread::spawn(move || serve_client(stream, sender)));
+        }
+    }
+
+    fn handle(&self, request: DaemonRequest) -> DaemonResponse {
+        match request {
+            DaemonRequest::Send { command } => match self.dev.send(&command) {
+                Ok(()) => DaemonResponse::Ok,
+                Err(e) => DaemonResponse::error(e),
+            },
+            DaemonRequest::Apply { config } => {
+                let report = self.dev.apply(&config);
+                DaemonResponse::Applied {
+                    applied: report.applied,
+                    skipped: report.skipped,
+                    failed: report
+                        .failed
+                        .into_iter()
+                        .map(|(field, e)| (field, e.to_string()))
+                        .collect(),
+                }
+            }
+            DaemonRequest::State => DaemonResponse::State {
+                state: self.dev.snapshot(),
+            },
+            // Taken care of by the client thread
+            DaemonRequest::Subscribe => DaemonResponse::error("unexpected subscription"),
+        }
+    }
+
+    fn add_subscriber(&mut self, stream: UnixStream) {
+        let (sender, events) = mpsc::sync_channel(SUBSCRIBER_BACKLOG);
+        self.clients
+            .push(std::thread::spawn(move || serve_subscriber(stream, events)));
+        self.subscribers.push(sender);
+    }
+
+    /// Pass the event to every subscriber, forgetting those that are gone or too far behind
+    /// (the device is never kept waiting for them).
+    fn broadcast(&mut self, event: Event) {
+        self.subscribers
+            .retain(|subscriber| subscriber.try_send(event).is_ok());
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Read the requests of a client and pass them to the daemon, until the client leaves,
+/// subscribes or sends a request longer than `MAX_REQUEST_LEN`.
+fn serve_client(stream: UnixStream, sender: Sender<Incoming>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match (&mut reader)
+            .take(MAX_REQUEST_LEN as u64)
+            .read_line(&mut line)
+        {
+            Ok(0) | Err(_) => return,
+            Ok(len) if len == MAX_REQUEST_LEN && !line.ends_with('\n') => {
+                let _ = write_line(&mut writer, &DaemonResponse::error("request too long"));
+                return;
+            }
+            Ok(_) => {}
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str(&line) {
+            Ok(DaemonRequest::Subscribe) => {
+                // From now on the daemon writes the events to the connection
+                let _ = sender.send(Incoming::Subscribe(writer));
+                return;
+            }
+            Ok(request) => {
+                let (reply, response) = mpsc::channel();
+                if sender
+                    .send(Incoming::Request(Box::new(request), reply))
+                    .is_err()
+                {
+                    return;
+                }
+                match response.recv() {
+                    Ok(response) => response,
+                    Err(_) => return,
+                }
+            }
+            Err(e) => DaemonResponse::error(format!("invalid request: {}", e)),
+        };
+        if write_line(&mut writer, &response).is_err() {
+            return;
+        }
+    }
+}
+
+/// Write the events to a subscriber, until it leaves or the daemon forgets it.
+fn serve_subscriber(mut stream: UnixStream, events: Receiver<Event>) {
+    if stream
+        .set_write_timeout(Some(SUBSCRIBER_WRITE_TIMEOUT))
+        .is_err()
+        || write_line(&mut stream, &DaemonResponse::Ok).is_err()
+    {
+        return;
+    }
+    for event in events {
+        if write_line(&mut stream, &DaemonResponse::Event { event }).is_err() {
+            return;
+        }
+    }
+}
+
+fn write_line(stream: &mut UnixStream, message: &impl Serialize) -> io::Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    stream.write_all(&line)
+}
+
+/// A connection to a `Daemon`.
+///
+/// ```no_run
+/// # use xencelabs_quick_keys::*;
+/// # fn main() -> QKResult<()> {
+/// let mut client = DaemonClient::connect(default_socket_path())?;
+/// client.send(&Command::SetRingColor { color: Color::RED })?;
+/// for event in client.subscribe()? {
+///     println!("{:?}", event?);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct DaemonClient {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl DaemonClient {
+    pub fn connect(path: impl AsRef<Path>) -> QKResult<Self> {
+        let writer = UnixStream::connect(path)?;
+        Ok(DaemonClient {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+        })
+    }
+
+    /// Send a request and wait for its response.
+    pub fn request(&mut self, request: &DaemonRequest) -> QKResult<DaemonResponse> {
+        write_line(&mut self.writer, request)?;
+        self.receive()
+    }
+
+    /// Send a command to the device, turning an error response into
+    /// `QKError::QKDaemonError`.
+    pub fn send(&mut self, command: &Command) -> QKResult<()> {
+        let request = DaemonRequest::Send {
+            command: command.clone(),
+        };
+        match self.request(&request)? {
+            DaemonResponse::Ok => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Receive the events of the device, until it is disconnected.
+    pub fn subscribe(mut self) -> QKResult<DaemonEvents> {
+        match self.request(&DaemonRequest::Subscribe)? {
+            DaemonResponse::Ok => Ok(DaemonEvents {
+                client: self,
+                done: false,
+            }),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    fn receive(&mut self) -> QKResult<DaemonResponse> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(QKError::QKConnectionError);
+        }
+        serde_json::from_str(&line).map_err(|e| QKError::QKParseError(e.to_string()))
+    }
+}
+
+fn unexpected(response: DaemonResponse) -> QKError {
+    match response {
+        DaemonResponse::Error { message } => QKError::QKDaemonError(message),
+        response => QKError::QKDaemonError(format!("unexpected response: {:?}", response)),
+    }
+}
+
+/// Blocking iterator over the events forwarded by a daemon (see `DaemonClient::subscribe`).
+///
+/// Like `QKDevice::events`, it ends after yielding `Event::Disconnected`.
+pub struct DaemonEvents {
+    client: DaemonClient,
+    done: bool,
+}
+
+impl Iterator for DaemonEvents {
+    type Item = QKResult<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.client.receive() {
+            Ok(DaemonResponse::Event { event }) => {
+                self.done = event == Event::Disconnected;
+                Some(Ok(event))
+            }
+            Ok(response) => Some(Err(unexpected(response))),
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_daemon {
+    use super::*;
+    use crate::color::Color;
+    use crate::msgs::ButtonState;
+    use crate::record::testing::{input, open, temp_path};
+    use crate::record::{Record, Replayer};
+    use crate::Subscriptions;
+
+    #[test]
+    fn it_should_use_tagged_json() {
+        let request: DaemonRequest = serde_json::from_str(
+            r#"{"request": "send", "command": {"SetKeyText": {"key": 0, "text": "Copy"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            request,
+            DaemonRequest::Send {
+                command: Command::SetKeyText {
+                    key: 0,
+                    text: "Copy".to_string()
+                }
+            }
+        );
+        assert_eq!(
+            serde_json::to_string(&DaemonRequest::Subscribe).unwrap(),
+            r#"{"request":"subscribe"}"#
+        );
+        let response = DaemonResponse::Event {
+            event: Event::Battery { percent: 50 },
+        };
+        let json = serde_json::to_string(&response).unwrap();
+        assert_eq!(
+            json,
+            r#"{"response":"event","event":{"Battery":{"percent":50}}}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<DaemonResponse>(&json).unwrap(),
+            response
+        );
+    }
+
+    #[test]
+    fn it_should_serve_several_clients() {
+        let pressed = Event::Button {
+            state: ButtonState {
+                button_0: true,
+                ..ButtonState::default()
+            },
+        };
+        let (dev, _) = open(
+            Replayer::new(vec![Record {
+                at: Duration::from_millis(300),
+                ..input(&pressed)
+            }])
+            .realtime(true),
+            Subscriptions::all(),
+        );
+        let path = temp_path("daemon");
+        let mut daemon = Daemon::bind(dev, &path).unwrap();
+        let server = std::thread::spawn(move || {
+            daemon.run().unwrap();
+            daemon.device().ring_color()
+        });
+
+        let events = DaemonClient::connect(&path).unwrap().subscribe().unwrap();
+        let mut client = DaemonClient::connect(&path).unwrap();
+        client
+            .send(&Command::SetRingColor { color: Color::RED })
+            .unwrap();
+        let response = client
+            .request(&DaemonRequest::Apply {
+                config: DeviceConfig {
+                    ring_color: Some(Color::RED),
+                    sleep_timeout: Some(5),
+                    ..DeviceConfig::default()
+                },
+            })
+            .unwrap();
+        assert_eq!(
+            response,
+            DaemonResponse::Applied {
+                applied: vec![ConfigField::SleepTimeout],
+                skipped: vec![ConfigField::RingColor],
+                failed: vec![],
+            }
+        );
+        match client.request(&DaemonRequest::State).unwrap() {
+            DaemonResponse::State { state } => assert_eq!(state.sleep_timeout, Some(5)),
+            response => panic!("unexpected response {:?}", response),
+        }
+        let refused = client.send(&Command::SetKeyText {
+            key: 9,
+            text: "nope".to_string(),
+        });
+        assert!(
+            matches!(&refused, Err(QKError::QKDaemonError(m)) if m.contains("out of range")),
+            "{:?}",
+            refused
+        );
+        let (other, _) = open(Replayer::new(vec![]), Subscriptions::none());
+        assert!(Daemon::bind(other, &path).is_err());
+        writeln!(client.writer, "not json").unwrap();
+        assert!(matches!(
+            client.receive().unwrap(),
+            DaemonResponse::Error { .. }
+        ));
+
+        let received = events.collect::<QKResult<Vec<Event>>>().unwrap();
+        assert_eq!(received, vec![pressed, Event::Disconnected]);
+        assert_eq!(server.join().unwrap(), Some(Color::RED));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn it_should_not_wait_for_stalled_subscribers() {
+        let battery = Record {
+            at: Duration::from_millis(200),
+            ..input(&Event::Battery { percent: 42 })
+        };
+        let (dev, _) = open(
+            Replayer::new(vec![battery; 5000]).realtime(true),
+            Subscriptions::all(),
+        );
+        let path = temp_path("daemon-stalled");
+        let mut daemon = Daemon::bind(dev, &path).unwrap();
+        let server = std::thread::spawn(move || daemon.run());
+        // Subscribes, then never reads
+        let mut stalled = UnixStream::connect(&path).unwrap();
+        writeln!(stalled, r#"{{"request": "subscribe"}}"#).unwrap();
+        let start = std::time::Instant::now();
+        server.join().unwrap().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    /// A daemon on the given socket, until the device disconnects after `lifetime`.
+    fn serve(path: &Path, lifetime: Duration) -> JoinHandle<QKResult<()>> {
+        let (dev, _) = open(
+            Replayer::new(vec![Record {
+                at: lifetime,
+                ..input(&Event::Battery { percent: 42 })
+            }])
+            .realtime(true),
+            Subscriptions::none(),
+        );
+        let mut daemon = Daemon::bind(dev, path).unwrap();
+        std::thread::spawn(move || daemon.run())
+    }
+
+    #[test]
+    fn it_should_disconnect_clients_sending_too_long_requests() {
+        let path = temp_path("daemon-long");
+        let server = serve(&path, Duration::from_millis(300));
+        let mut client = DaemonClient::connect(&path).unwrap();
+        client
+            .writer
+            .write_all(&vec![b' '; MAX_REQUEST_LEN + 1])
+            .unwrap();
+        assert_eq!(
+            client.receive().unwrap(),
+            DaemonResponse::error("request too long")
+        );
+        assert!(client.receive().is_err());
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn it_should_refuse_clients_beyond_the_limit() {
+        let path = temp_path("daemon-full");
+        let server = serve(&path, Duration::from_millis(300));
+        // Accepted in the order they connect
+        let served = (0..MAX_CLIENTS)
+            .map(|_| UnixStream::connect(&path).unwrap())
+            .collect::<Vec<UnixStream>>();
+        let mut refused = DaemonClient::connect(&path).unwrap();
+        assert_eq!(
+            refused.receive().unwrap(),
+            DaemonResponse::error("too many clients")
+        );
+        drop(served);
+        server.join().unwrap().unwrap();
+    }
+}
@@ -9,6 +9,83 @@ pub enum QKError {
     QKDeviceNotFound,
     #[error("Quick Keys connection error")]
     QKConnectionError,
+    #[error("Quick Keys device disconnected")]
+    QKDisconnected,
+    #[error("Invalid color: {0}")]
+    QKInvalidColor(String),
+    #[error("Invalid command: {0}")]
+    QKInvalidCommand(String),
+    #[error("Daemon error: {0}")]
+    QKDaemonError(String),
+    #[error("Parse error: {0}")]
+    QKParseError(String),
+    #[error("I/O error: {0}")]
+    QKIoError(#[from] std::io::Error),
     #[error("Quick Keys HID error: {0}")]
-    QKHidError(#[from] HidError),
+    QKHidError(#[source] HidError),
+}
+
+impl From<HidError> for QKError {
+    fn from(e: HidError) -> Self {
+        if is_disconnection(&e) {
+            QKError::QKDisconnected
+        } else {
+            QKError::QKHidError(e)
+        }
+    }
+}
+
+/// Messages used by the different hidapi backends when the device is gone
+const DISCONNECTION_MESSAGES: &[&str] = &[
+    "no such device",
+    "input/output error",
+    "device not configured",
+    "device is not connected",
+    "device disconnected",
+    "0xe00002c0",
+];
+
+/// Whether a hidapi error means that the device has been unplugged (or switched off).
+fn is_disconnection(e: &HidError) -> bool {
+    let message = match e {
+        HidError::HidApiError { message } => message.to_lowercase(),
+        HidError::IoError { error } => {
+            if matches!(
+                error.kind(),
+                std::io::ErrorKind::NotConnected | std::io::ErrorKind::BrokenPipe
+            ) {
+                return true;
+            }
+            error.to_string().to_lowercase()
+        }
+        _ => return false,
+    };
+    DISCONNECTION_MESSAGES.iter().any(|m| message.contains(m))
+}
+
+#[cfg(test)]
+mod tests_error {
+    use super::*;
+
+    #[test]
+    fn it_should_classify_disconnections() {
+        let unplugged = HidError::HidApiError {
+            message: "hid_read: No such device".to_string(),
+        };
+        assert!(matches!(unplugged.into(), QKError::QKDisconnected));
+        let broken = HidError::IoError {
+            error: std::io::ErrorKind::BrokenPipe.into(),
+        };
+        assert!(matches!(broken.into(), QKError::QKDisconnected));
+    }
+
+    #[test]
+    fn it_should_keep_other_errors() {
+        let other = HidError::HidApiError {
+            message: "Resource temporarily unavailable".to_string(),
+        };
+        assert!(matches!(other.into(), QKError::QKHidError(_)));
+        let zero = HidError::InvalidZeroSizeData;
+        assert!(matches!(zero.into(), QKError::QKHidError(_)));
+    }
 }
@@ -0,0 +1,371 @@
+use crate::color::Color;
+use crate::config::DeviceConfig;
+use crate::msgs::{ButtonState, Event, Key, WheelDirection};
+use crate::{QKDevice, QKResult};
+
+/// Several keys pressed together
+#[derive(Debug, PartialEq, Clone)]
+pub struct Chord {
+    pub keys: Vec<Key>,
+    pub action: String,
+}
+
+/// One set of labels, ring color and actions for the eight keys
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Layer {
+    /// Shown as an overlay when the layer is activated
+    pub name: String,
+    pub labels: [String; 8],
+    pub ring_color: Color,
+    /// Action triggered by each key (what it means is up to the application)
+    pub actions: [Option<String>; 8],
+    /// Actions triggered by pressing several keys together
+    pub chords: Vec<Chord>,
+    /// Action triggered by turning the wheel left
+    pub wheel_left: Option<String>,
+    /// Action triggered by turning the wheel right
+    pub wheel_right: Option<String>,
+}
+
+/// Several layers of keys, cycled with a switch key
+#[derive(Debug, PartialEq, Clone)]
+pub struct Profile {
+    /// Settings applied when the profile is activated (labels and ring color come from the layers)
+    pub device: DeviceConfig,
+    pub layers: Vec<Layer>,
+    /// Key activating the next layer
+    pub switch_key: Key,
+    /// Seconds the name of a newly activated layer is shown (0 disables it)
+    pub overlay_seconds: u8,
+}
+
+impl Profile {
+    /// A profile switching between the given layers with the extra button.
+    pub fn new(layers: Vec<Layer>) -> Self {
+        Profile {
+            device: DeviceConfig::default(),
+            layers,
+            switch_key: Key::Extra,
+            overlay_seconds: 1,
+        }
+    }
+}
+
+/// What happened when a `LayerSwitcher` handled an event
+#[derive(Debug, PartialEq, Clone)]
+pub enum LayerEvent {
+    /// Another layer is now active
+    Switched { layer: usize },
+    /// A key with an action was pressed
+    Action { key: u8, action: String },
+    /// All the keys of a chord are pressed
+    Chord { keys: Vec<Key>, action: String },
+    /// The wheel turned in a direction with an action
+    Wheel {
+        direction: WheelDirection,
+        action: String,
+    },
+}
+
+/// Apply a `Profile` to a device: feed it every event read from the device, it switches
+/// layers when the switch key is pressed and tells which actions were triggered.
+///
+/// ```no_run
+/// # use xencelabs_quick_keys::*;
+/// # fn main() -> QKResult<()> {
+/// # let profile = Profile::new(vec![Layer::default()]);
+/// let dev = QKDevice::open(hidapi::HidApi::new()?, ConnectionMode::Auto)?;
+/// let mut layers = LayerSwitcher::new(profile);
+/// layers.activate(&dev)?;
+/// for event in dev.events() {
+///     for action in layers.handle(&dev, &event?)? {
+///         println!("{:?}", action);
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct LayerSwitcher {
+    profile: Profile,
+    current: usize,
+    previous: ButtonState,
+}
+
+impl LayerSwitcher {
+    pub fn new(profile: Profile) -> Self {
+        LayerSwitcher {
+            profile,
+            current: 0,
+            previous: ButtonState::default(),
+        }
+    }
+
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    /// Index of the active layer.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// The active layer (`None` if the profile has no layers).
+    pub fn layer(&self) -> Option<&Layer> {
+        self.profile.layers.get(self.current)
+    }
+
+    /// Send the device settings of the profile and the labels and ring color of the active
+    /// layer to the device.
+    pub fn activate(&self, dev: &QKDevice) -> QKResult<()> {
+        if let Some((_, e)) = dev.apply(&self.profile.device).failed.into_iter().next() {
+            return Err(e);
+        }
+        self.show_layer(dev)
+    }
+
+    /// Send the labels and ring color of the active layer to the device.
+    fn show_layer(&self, dev: &QKDevice) -> QKResult<()> {
+        let Some(layer) = self.layer() else {
+            return Ok(());
+        };
+        let labels = [0, 1, 2, 3, 4, 5, 6, 7].map(|k| layer.labels[k].as_str());
+        dev.refresh_all_key_texts(labels)?;
+        dev.set_ring_color(layer.ring_color)
+    }
+
+    /// Switch to another profile, only sending what differs from the current one.
+    ///
+    /// The active layer is kept if the new profile has it, and keys held down don't
+    /// trigger their actions again.
+    pub fn set_profile(&mut self, dev: &QKDevice, profile: Profile) -> QKResult<()> {
+        self.profile = profile;
+        if self.current >= self.profile.layers.len() {
+            self.current = 0;
+        }
+        let mut config = self.profile.device.clone();
+        if let Some(layer) = self.layer() {
+            config.ring_color = Some(layer.ring_color);
+            config.key_texts = layer.labels.clone().map(Some);
+        }
+        match dev.apply(&config).failed.into_iter().next() {
+            Some((_, e)) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Activate the given layer, showing its name.  Out of range indexes are ignored.
+    pub fn set_layer(&mut self, dev: &QKDevice, layer: usize) -> QKResult<()> {
+        if layer >= self.profile.layers.len() {
+            return Ok(());
+        }
+        self.current = layer;
+        self.show_layer(dev)?;
+        if self.profile.overlay_seconds > 0 {
+            let name = &self.profile.layers[layer].name;
+            dev.show_overlay_text(name, self.profile.overlay_seconds)?;
+        }
+        Ok(())
+    }
+
+    /// Handle an event read from the device.
+    ///
+    /// Only presses count: holding a key triggers its action (or switches layers) once.  The
+    /// keys completing a chord don't trigger their own actions.
+    pub fn handle(&mut self, dev: &QKDevice, event: &Event) -> QKResult<Vec<LayerEvent>> {
+        let state = match event {
+            Event::Button { state } | Event::Wheel { state, .. } => *state,
+            Event::Disconnected => ButtonState::default(),
+            _ => return Ok(vec![]),
+        };
+        let pressed = state.pressed_since(&self.previous);
+        self.previous = state;
+
+        let mut events = Vec::new();
+        if let (Event::Wheel { direction, .. }, Some(layer)) = (event, self.layer()) {
+            let action = match direction {
+                WheelDirection::Left => &layer.wheel_left,
+                WheelDirection::Right => &layer.wheel_right,
+            };
+            if let Some(action) = action {
+                events.push(LayerEvent::Wheel {
+                    direction: *direction,
+                    action: action.clone(),
+                });
+            }
+        }
+        let mut consumed = Vec::new();
+        for chord in self.layer().map_or(&[][..], |l| &l.chords) {
+            let complete = chord.keys.iter().all(|&k| state.is_pressed(k));
+            if complete && chord.keys.iter().any(|k| pressed.contains(k)) {
+                consumed.extend(chord.keys.iter().copied());
+                events.push(LayerEvent::Chord {
+                    keys: chord.keys.clone(),
+                    action: chord.action.clone(),
+                });
+            }
+        }
+        for key in pressed.into_iter().filter(|k| !consumed.contains(k)) {
+            if key == self.profile.switch_key && !self.profile.layers.is_empty() {
+                let next = (self.current + 1) % self.profile.layers.len();
+                self.set_layer(dev, next)?;
+                events.push(LayerEvent::Switched { layer: next });
+            } else if let Key::Button(k) = key {
+                if let Some(action) = self.layer().and_then(|l| l.actions[k as usize].clone()) {
+                    events.push(LayerEvent::Action { key: k, action });
+                }
+            }
+        }
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests_layers {
+    use super::*;
+    use crate::msgs::*;
+    use crate::record::testing::replay;
+
+    fn layer(name: &str, color: Color) -> Layer {
+        let mut layer = Layer {
+            name: name.to_string(),
+            labels: [0, 1, 2, 3, 4, 5, 6, 7].map(|k| format!("{}{}", name, k)),
+            ring_color: color,
+            ..Layer::default()
+        };
+        layer.actions[0] = Some(format!("{}-action", name));
+        layer
+    }
+
+    fn press(state: ButtonState) -> Event {
+        Event::Button { state }
+    }
+
+    #[test]
+    fn it_should_cycle_layers_with_the_switch_key() {
+        let (dev, replayer) = replay(&[]);
+        let profile = Profile::new(vec![layer("a", Color::RED), layer("b", Color::BLUE)]);
+        let mut layers = LayerSwitcher::new(profile);
+        let extra = ButtonState {
+            button_extra: true,
+            ..ButtonState::default()
+        };
+
+        let events = layers.handle(&dev, &press(extra)).unwrap();
+        assert_eq!(events, vec![LayerEvent::Switched { layer: 1 }]);
+        assert_eq!(dev.key_text(3).as_deref(), Some("b3"));
+        assert_eq!(dev.ring_color(), Some(Color::BLUE));
+        assert_eq!(dev.overlay().unwrap().text, "b");
+        // 8 labels, the ring color and the overlay
+        assert_eq!(replayer.written().len(), 10);
+
+        // Holding the key doesn't switch again
+        assert_eq!(layers.handle(&dev, &press(extra)).unwrap(), vec![]);
+        layers.handle(&dev, &press(ButtonState::default())).unwrap();
+        layers.handle(&dev, &press(extra)).unwrap();
+        assert_eq!(layers.current(), 0);
+        assert_eq!(dev.ring_color(), Some(Color::RED));
+    }
+
+    #[test]
+    fn it_should_report_the_actions_of_the_current_layer() {
+        let (dev, _) = replay(&[]);
+        let mut profile = Profile::new(vec![layer("a", Color::RED), layer("b", Color::BLUE)]);
+        profile.switch_key = Key::Wheel;
+        let mut layers = LayerSwitcher::new(profile);
+        let key0 = ButtonState {
+            button_0: true,
+            ..ButtonState::default()
+        };
+        let events = layers.handle(&dev, &press(key0)).unwrap();
+        assert_eq!(
+            events,
+            vec![LayerEvent::Action {
+                key: 0,
+                action: "a-action".to_string()
+            }]
+        );
+        // Keys without an action are ignored
+        let key1 = ButtonState {
+            button_1: true,
+            ..ButtonState::default()
+        };
+        assert_eq!(layers.handle(&dev, &press(key1)).unwrap(), vec![]);
+
+        let wheel = ButtonState {
+            button_wheel: true,
+            ..ButtonState::default()
+        };
+        layers.handle(&dev, &press(wheel)).unwrap();
+        let events = layers.handle(&dev, &press(key0)).unwrap();
+        assert_eq!(
+            events,
+            vec![LayerEvent::Action {
+                key: 0,
+                action: "b-action".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn it_should_only_send_what_changed_with_a_new_profile() {
+        let (dev, replayer) = replay(&[]);
+        let mut layers = LayerSwitcher::new(Profile::new(vec![layer("a", Color::RED)]));
+        layers.activate(&dev).unwrap();
+        let before = replayer.written().len();
+
+        let mut edited = layer("a", Color::RED);
+        edited.labels[4] = "new".to_string();
+        layers
+            .set_profile(&dev, Profile::new(vec![edited]))
+            .unwrap();
+        assert_eq!(replayer.written().len(), before + 1);
+        assert_eq!(dev.key_text(4).as_deref(), Some("new"));
+        assert_eq!(layers.layer().unwrap().labels[4], "new");
+    }
+
+    #[test]
+    fn it_should_report_chords_and_wheel_actions() {
+        let (dev, _) = replay(&[]);
+        let mut first = layer("a", Color::RED);
+        first.chords.push(Chord {
+            keys: vec![Key::Button(0), Key::Extra],
+            action: "chord".to_string(),
+        });
+        first.wheel_left = Some("left".to_string());
+        let mut layers = LayerSwitcher::new(Profile::new(vec![first, layer("b", Color::BLUE)]));
+
+        let key0 = ButtonState {
+            button_0: true,
+            ..ButtonState::default()
+        };
+        layers.handle(&dev, &press(key0)).unwrap();
+        let both = ButtonState {
+            button_extra: true,
+            ..key0
+        };
+        // The extra button completes the chord instead of switching layers
+        let events = layers.handle(&dev, &press(both)).unwrap();
+        assert_eq!(
+            events,
+            vec![LayerEvent::Chord {
+                keys: vec![Key::Button(0), Key::Extra],
+                action: "chord".to_string()
+            }]
+        );
+        assert_eq!(layers.current(), 0);
+
+        let wheel = Event::Wheel {
+            direction: WheelDirection::Left,
+            state: both,
+        };
+        let events = layers.handle(&dev, &wheel).unwrap();
+        assert_eq!(
+            events,
+            vec![LayerEvent::Wheel {
+                direction: WheelDirection::Left,
+                action: "left".to_string()
+            }]
+        );
+    }
+}
@@ -1,14 +1,54 @@
 #![allow(dead_code)]
 extern crate hidapi;
 
-use hidapi::{HidApi, HidDevice};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
+use hidapi::{DeviceInfo, HidApi, HidDevice};
+
+mod battery;
+mod calibration;
+mod color;
+mod command;
+mod config;
+mod context;
+#[cfg(all(unix, feature = "serde"))]
+mod daemon;
 mod error;
+mod layers;
 mod msgs;
+#[cfg(feature = "serde")]
+mod profile;
+mod queue;
+mod record;
+mod state;
+mod transport;
+#[cfg(feature = "watch")]
+mod watch;
 
+pub use battery::{BatteryAlert, BatteryMonitor, BatteryReading};
+pub use calibration::Calibration;
+pub use color::Color;
+pub use command::{decode_command, Command, DecodedFrame, OverlayChunk};
+pub use config::{ApplyReport, ConfigField, DeviceConfig};
+#[cfg(unix)]
+pub use context::SocketContext;
+pub use context::{ContextProvider, FileContext, ProfileSelector};
+#[cfg(all(unix, feature = "serde"))]
+pub use daemon::{default_socket_path, Daemon, DaemonClient, DaemonEvents, DaemonRequest, DaemonResponse};
 pub use error::QKError;
-pub use msgs::{ButtonState, Event, ScreenOrientation, WheelDirection, ScreenBrightness, WheelSpeed};
-use msgs::*;
+pub use layers::{Chord, Layer, LayerEvent, LayerSwitcher, Profile};
+pub use msgs::{ButtonState, Event, EventClass, InputReport, Key, ScreenOrientation, WheelDirection, ScreenBrightness, WheelSpeed, INPUT_REPORT_SIZE};
+pub use msgs::{encode_event, process_input};
+pub use queue::{OutputQueue, DEFAULT_MAX_RATE};
+pub use record::{read_recording, Direction, Record, Recorder, Replayer};
+pub use state::{ActiveOverlay, DeviceState};
+pub use transport::Transport;
+#[cfg(feature = "watch")]
+pub use watch::ProfileWatcher;
+use state::StateTracker;
 
 /// Connection method (cable, wireless, or automatic...)
 #[derive(Debug, PartialEq, Clone, Copy, Default)]
@@ -20,85 +60,431 @@ pub enum ConnectionMode {
     Auto,
 }
 
+impl FromStr for ConnectionMode {
+    type Err = QKError;
+
+    /// Parse `auto`, `wired` or `wireless`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ConnectionMode::Auto),
+            "wired" => Ok(ConnectionMode::Wired),
+            "wireless" => Ok(ConnectionMode::Wireless),
+            _ => Err(QKError::QKParseError(format!(
+                "invalid connection mode {:?}, expected auto, wired or wireless",
+                s
+            ))),
+        }
+    }
+}
+
 pub type QKResult<T> = Result<T, QKError>;
 
+/// The classes of events a device is subscribed to
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Subscriptions {
+    pub keys: bool,
+    pub battery: bool,
+}
 
-/// Use to send and receive commands from a particular Quick Keys device.
-pub struct QKDevice {
-    device: HidDevice,
+impl Subscriptions {
+    /// Subscribed to every class of events.
+    pub fn all() -> Self {
+        Subscriptions {
+            keys: true,
+            battery: true,
+        }
+    }
+
+    /// Not subscribed to any class of events.
+    pub fn none() -> Self {
+        Subscriptions::default()
+    }
+
+    /// Whether the given class of events is part of the subscriptions.
+    pub fn contains(&self, class: EventClass) -> bool {
+        match class {
+            EventClass::Keys => self.keys,
+            EventClass::Battery => self.battery,
+        }
+    }
+
+    fn set(&mut self, class: EventClass, enabled: bool) {
+        match class {
+            EventClass::Keys => self.keys = enabled,
+            EventClass::Battery => self.battery = enabled,
+        }
+    }
 }
 
-impl QKDevice {
+/// Options used to connect to a Quick Keys device.
+///
+/// ```no_run
+/// # use xencelabs_quick_keys::*;
+/// # fn main() -> QKResult<()> {
+/// // Drive the display only, without consuming any input.
+/// let dev = OpenOptions::new()
+///     .mode(ConnectionMode::Auto)
+///     .subscriptions(Subscriptions::none())
+///     .open(hidapi::HidApi::new()?)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct OpenOptions {
+    mode: ConnectionMode,
+    subscriptions: Subscriptions,
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        OpenOptions {
+            mode: ConnectionMode::default(),
+            subscriptions: Subscriptions::all(),
+        }
+    }
+}
+
+impl OpenOptions {
+    /// Default options: wired connection, subscribed to every class of events.
+    pub fn new() -> Self {
+        OpenOptions::default()
+    }
+
+    /// Set the connection method.
+    pub fn mode(mut self, mode: ConnectionMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Subscribe (or not) to the given class of events when connecting.
+    pub fn subscribe(mut self, class: EventClass, enabled: bool) -> Self {
+        self.subscriptions.set(class, enabled);
+        self
+    }
+
+    /// Set all the subscriptions made when connecting.
+    pub fn subscriptions(mut self, subscriptions: Subscriptions) -> Self {
+        self.subscriptions = subscriptions;
+        self
+    }
+
     /// Search and connect to a Quick Keys device using a HidApi instance.
-    pub fn open(hidapi: HidApi, mode: ConnectionMode) -> QKResult<Self> {
-        let this = hidapi
+    pub fn open(&self, hidapi: HidApi) -> QKResult<QKDevice> {
+        let device = self.connect(&hidapi)?;
+        self.open_transport(device)
+    }
+
+    /// Search and open the HID device of a Quick Keys, without talking to it yet.
+    pub fn connect(&self, hidapi: &HidApi) -> QKResult<HidDevice> {
+        let mode = self.mode;
+        hidapi
             .device_list()
             .find(|&dev| {
-                dev.vendor_id() == 0x28BD
-                    && ((dev.product_id() == 0x5202 && mode != ConnectionMode::Wireless)
-                        || (dev.product_id() == 0x5204 && mode != ConnectionMode::Wired))
-                    && dev.usage() == 1
-                    && dev.usage_page() == 0xff0a
-            })
-            .map(|dev| match dev.open_device(&hidapi) {
-                Ok(d) => Ok(QKDevice { device: d }),
-                Err(_) => Err(QKError::QKConnectionError),
+                matches!(device_mode(dev), Some(m) if mode == ConnectionMode::Auto || mode == m)
             })
-            .or_else(|| Some(Err(QKError::QKDeviceNotFound)))
-            .unwrap()?;
-        this.device.write(&msg_subscribe_to_key_events())?;
-        this.device.write(&msg_subscribe_to_battery())?;
+            .map(|dev| dev.open_device(hidapi).map_err(|_| QKError::QKConnectionError))
+            .unwrap_or(Err(QKError::QKDeviceNotFound))
+    }
+
+    /// Use the given transport (e.g. a `Recorder` or a `Replayer`) as the device.
+    pub fn open_transport(&self, transport: impl Transport + 'static) -> QKResult<QKDevice> {
+        let this = QKDevice {
+            device: Box::new(transport),
+            calibration: Calibration::default(),
+            pending: RefCell::new(VecDeque::new()),
+            subscriptions: Cell::new(Subscriptions::none()),
+            state: RefCell::new(StateTracker::default()),
+        };
+        for class in [EventClass::Keys, EventClass::Battery] {
+            if self.subscriptions.contains(class) {
+                this.subscribe(class)?;
+            }
+        }
         Ok(this)
     }
+}
+
+/// How a HID device is connected, if it is (the right interface of) a Quick Keys.
+fn device_mode(dev: &DeviceInfo) -> Option<ConnectionMode> {
+    if dev.vendor_id() != 0x28BD || dev.usage() != 1 || dev.usage_page() != 0xff0a {
+        return None;
+    }
+    match dev.product_id() {
+        0x5202 => Some(ConnectionMode::Wired),
+        0x5204 => Some(ConnectionMode::Wireless),
+        _ => None,
+    }
+}
+
+/// A Quick Keys device found by `list_devices`
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceListing {
+    /// Platform specific path of the HID device
+    pub path: String,
+    /// `Wired` or `Wireless` (through the USB receiver)
+    pub mode: ConnectionMode,
+    pub serial_number: Option<String>,
+}
+
+/// List the Quick Keys devices available through a HidApi instance.
+pub fn list_devices(hidapi: &HidApi) -> Vec<DeviceListing> {
+    hidapi
+        .device_list()
+        .filter_map(|dev| {
+            Some(DeviceListing {
+                path: dev.path().to_string_lossy().into_owned(),
+                mode: device_mode(dev)?,
+                serial_number: dev.serial_number().map(str::to_string),
+            })
+        })
+        .collect()
+}
+
+/// Use to send and receive commands from a particular Quick Keys device.
+pub struct QKDevice {
+    device: Box<dyn Transport>,
+    calibration: Calibration,
+    pending: RefCell<VecDeque<InputReport>>,
+    subscriptions: Cell<Subscriptions>,
+    state: RefCell<StateTracker>,
+}
+
+impl QKDevice {
+    /// Search and connect to a Quick Keys device using a HidApi instance, subscribing to
+    /// every class of events (see `OpenOptions` for finer control).
+    pub fn open(hidapi: HidApi, mode: ConnectionMode) -> QKResult<Self> {
+        OpenOptions::new().mode(mode).open(hidapi)
+    }
+
+    //
+    // Subscriptions
+    //
+
+    /// Ask the device to report the given class of events.
+    pub fn subscribe(&self, class: EventClass) -> QKResult<()> {
+        self.send(&Command::Subscribe { class })
+    }
+
+    /// Stop delivering the given class of events.
+    ///
+    /// The protocol has no known message to cancel a subscription, so the device keeps
+    /// sending these reports, but `read` and `read_timeout` discard them.
+    pub fn unsubscribe(&self, class: EventClass) {
+        let mut subscriptions = self.subscriptions.get();
+        subscriptions.set(class, false);
+        self.subscriptions.set(subscriptions);
+    }
+
+    /// The classes of events currently delivered by `read` and `read_timeout`.
+    pub fn subscriptions(&self) -> Subscriptions {
+        self.subscriptions.get()
+    }
+
+    /// Whether an event belongs to a class we are subscribed to (unknown events always are).
+    fn is_wanted(&self, event: &Event) -> bool {
+        match event.class() {
+            Some(class) => self.subscriptions.get().contains(class),
+            None => true,
+        }
+    }
 
     //
     // Output Api
     //
 
+    /// Send a command to the device.
+    ///
+    /// Ring colors go through the device calibration first (see `set_calibration`).
+    pub fn send(&self, command: &Command) -> QKResult<()> {
+        let frames = match command {
+            Command::SetRingColor { color } => Command::SetRingColor {
+                color: self.calibration.apply(*color),
+            }
+            .encode(),
+            _ => command.encode(),
+        }?;
+        for frame in &frames {
+            self.device.write(frame)?;
+        }
+        self.track(command);
+        Ok(())
+    }
+
+    /// Write a frame to the device as is, e.g. to try out undocumented commands.
+    ///
+    /// The frame must start with the report id (0x02).  Unlike `send`, colors are not
+    /// calibrated, but frames of known commands are still recognized (see `decode_command`)
+    /// so that sending a raw subscription keeps `subscriptions` up to date.
+    pub fn send_raw(&self, frame: &[u8; 32]) -> QKResult<()> {
+        self.device.write(frame)?;
+        match decode_command(frame) {
+            Some(DecodedFrame::Command(command)) => self.track(&command),
+            Some(DecodedFrame::OverlayChunk(chunk)) => {
+                self.state.borrow_mut().overlay_chunk(&chunk, Instant::now())
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Keep track of the effects of a command sent to the device.
+    fn track(&self, command: &Command) {
+        if let Command::Subscribe { class } = command {
+            let mut subscriptions = self.subscriptions.get();
+            subscriptions.set(*class, true);
+            self.subscriptions.set(subscriptions);
+        }
+        self.state.borrow_mut().command(command, Instant::now());
+    }
+
+    /// Apply a whole configuration, sending only the settings that differ from what was
+    /// last set (see `snapshot`).
+    ///
+    /// Every setting is attempted even if some fail; the report tells which ones were sent,
+    /// skipped or failed.
+    pub fn apply(&self, config: &DeviceConfig) -> ApplyReport {
+        let mut report = ApplyReport::default();
+        for (field, command) in config.commands() {
+            if config::is_in_place(&command, self.state.borrow().state()) {
+                report.skipped.push(field);
+                continue;
+            }
+            match self.send(&command) {
+                Ok(()) => report.applied.push(field),
+                Err(e) => report.failed.push((field, e)),
+            }
+        }
+        report
+    }
+
     /// Rotate the screen to the given angle.
     pub fn set_screen_orientation(&self, orientation: ScreenOrientation) -> QKResult<()> {
-        self.device.write(&msg_rotate_screen(orientation))?;
-        Ok(())
+        self.send(&Command::SetScreenOrientation { orientation })
     }
 
     /// Set screen brightness to the given level.
     pub fn set_screen_brightness(&self, level: ScreenBrightness) -> QKResult<()> {
-        self.device.write(&msg_set_screen_brightness(level))?;
-        Ok(())
+        self.send(&Command::SetScreenBrightness { level })
     }
 
 
     /// Set the wheel speed to the given value.
     pub fn set_wheel_speed(&self, speed: WheelSpeed) -> QKResult<()> {
-        self.device.write(&msg_set_wheel_speed(speed))?;
-        Ok(())
+        self.send(&Command::SetWheelSpeed { speed })
     }
 
     /// Switch off the device after the given amount of minutes (after connection is lost).
     pub fn set_sleep_timeout(&self, minutes: u8) -> QKResult<()> {
-        self.device.write(&msg_set_sleep_timeout(minutes))?;
-        Ok(())
+        self.send(&Command::SetSleepTimeout { minutes })
     }
 
-    /// Set the color of the LED ring of the wheel to the given RGB value.
-    pub fn set_ring_color(&self, red: u8, green: u8, blue: u8) -> QKResult<()> {
-        self.device.write(&msg_set_wheel_color(red, green, blue))?;
-        Ok(())
+    /// Set the color correction applied by `set_ring_color`.
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration = calibration;
+    }
+
+    /// Return the color correction applied by `set_ring_color`.
+    pub fn calibration(&self) -> &Calibration {
+        &self.calibration
+    }
+
+    /// Set the color of the LED ring of the wheel (a `Color` or any `(red, green, blue)` tuple).
+    /// The color goes through the device calibration first (see `set_calibration`).
+    pub fn set_ring_color(&self, color: impl Into<Color>) -> QKResult<()> {
+        self.send(&Command::SetRingColor {
+            color: color.into(),
+        })
     }
 
     /// Set the text label for the given key (0-7).  (max 8 characters, ATM).
     pub fn set_key_text(&self, key: u8, text: &str) -> QKResult<()> {
-        self.device.write(&msg_set_key_text(key, text))?;
-        Ok(())
+        self.send(&Command::SetKeyText {
+            key,
+            text: text.to_string(),
+        })
+    }
+
+    /// Set the labels of all the keys, only sending those that differ from what is
+    /// currently displayed (see `key_text`).
+    ///
+    /// Returns the number of labels actually sent.
+    pub fn set_all_key_texts(&self, texts: [&str; 8]) -> QKResult<usize> {
+        self.send_key_texts(texts, false)
     }
 
+    /// Like `set_all_key_texts`, but send every label even if it is already displayed
+    /// (e.g. after the device was reconnected).
+    pub fn refresh_all_key_texts(&self, texts: [&str; 8]) -> QKResult<usize> {
+        self.send_key_texts(texts, true)
+    }
+
+    fn send_key_texts(&self, texts: [&str; 8], force: bool) -> QKResult<usize> {
+        let mut sent = 0;
+        for (key, text) in texts.iter().enumerate() {
+            let command = Command::SetKeyText {
+                key: key as u8,
+                text: text.to_string(),
+            };
+            if force || !config::is_in_place(&command, self.state.borrow().state()) {
+                self.send(&command)?;
+                sent += 1;
+            }
+        }
+        Ok(sent)
+    }
 
     /// Show a text overlay for a fix amount of time (max 32 characters).
     pub fn show_overlay_text(&self, text: &str, seconds: u8) -> QKResult<()> {
-        for chunk in &msgs_show_overlay_text(seconds, text) {
-            self.device.write(chunk)?;
-        }
-        Ok(())
+        self.send(&Command::ShowOverlayText {
+            text: text.to_string(),
+            seconds,
+        })
+    }
+
+    //
+    // State
+    //
+
+    /// Last orientation set with `set_screen_orientation`.
+    pub fn screen_orientation(&self) -> Option<ScreenOrientation> {
+        self.state.borrow().state().orientation
+    }
+
+    /// Last brightness set with `set_screen_brightness`.
+    pub fn screen_brightness(&self) -> Option<ScreenBrightness> {
+        self.state.borrow().state().brightness
+    }
+
+    /// Last speed set with `set_wheel_speed`.
+    pub fn wheel_speed(&self) -> Option<WheelSpeed> {
+        self.state.borrow().state().wheel_speed
+    }
+
+    /// Last timeout set with `set_sleep_timeout`.
+    pub fn sleep_timeout(&self) -> Option<u8> {
+        self.state.borrow().state().sleep_timeout
+    }
+
+    /// Last color set with `set_ring_color` (as requested, before calibration).
+    pub fn ring_color(&self) -> Option<Color> {
+        self.state.borrow().state().ring_color
+    }
+
+    /// Last text set with `set_key_text` for the given key (0-7).
+    pub fn key_text(&self, key: u8) -> Option<String> {
+        self.state.borrow().state().key_texts.get(key as usize)?.clone()
+    }
+
+    /// The overlay currently shown by `show_overlay_text`, if it hasn't expired yet.
+    pub fn overlay(&self) -> Option<ActiveOverlay> {
+        self.state.borrow().overlay(Instant::now())
+    }
+
+    /// Everything last set on the device.
+    pub fn snapshot(&self) -> DeviceState {
+        self.state.borrow().snapshot(Instant::now())
     }
 
     //
@@ -107,23 +493,309 @@ impl QKDevice {
 
     /// Set the blocking mode (see hidapi for details).
     pub fn set_blocking_mode(&self, blocking: bool) -> QKResult<()> {
-        self.device.set_blocking_mode(blocking).map_err(QKError::QKHidError)
+        Ok(self.device.set_blocking_mode(blocking)?)
     }
 
     /// Read the next Event.  By default in blocks (unless set_blocking_mode(false)).
-    pub fn read(&self) -> QKResult<Event> {
-        let mut buf = [0u8; 10];
-        self.device.read(&mut buf[..])?;
-        Ok(process_input(&buf))
+    ///
+    /// Returns `None` if the device is in non-blocking mode and no event was available.
+    pub fn read(&self) -> QKResult<Option<Event>> {
+        Ok(self.read_report()?.map(|r| r.event))
+    }
+
+
+    /// Try to read the next Event in the next number of milliseconds (-1 waits forever).
+    ///
+    /// Returns `None` if no event arrived in time.
+    pub fn read_timeout(&self, timeout: i32) -> QKResult<Option<Event>> {
+        Ok(self.read_report_timeout(timeout)?.map(|r| r.event))
     }
 
+    /// Like `read`, but return the whole input report along with the decoded Event.
+    pub fn read_report(&self) -> QKResult<Option<InputReport>> {
+        if let Some(report) = self.pop_pending() {
+            return Ok(Some(report));
+        }
+        loop {
+            let mut buf = [0u8; INPUT_REPORT_SIZE];
+            let len = self.device.read(&mut buf[..])?;
+            if len == 0 {
+                return Ok(None);
+            }
+            let report = InputReport::new(buf, len);
+            if self.is_wanted(&report.event) {
+                return Ok(Some(report));
+            }
+        }
+    }
+
+    /// Like `read_timeout`, but return the whole input report along with the decoded Event.
+    pub fn read_report_timeout(&self, timeout: i32) -> QKResult<Option<InputReport>> {
+        if let Some(report) = self.pop_pending() {
+            return Ok(Some(report));
+        }
+        let deadline = (timeout >= 0).then(|| Instant::now() + Duration::from_millis(timeout as u64));
+        loop {
+            let remaining = deadline.map_or(-1, |d| {
+                d.saturating_duration_since(Instant::now()).as_millis() as i32
+            });
+            let mut buf = [0u8; INPUT_REPORT_SIZE];
+            match self.device.read_timeout(&mut buf[..], remaining) {
+                Ok(0) => return Ok(None),
+                Ok(len) => {
+                    let report = InputReport::new(buf, len);
+                    if self.is_wanted(&report.event) {
+                        return Ok(Some(report));
+                    }
+                    if matches!(deadline, Some(d) if Instant::now() >= d) {
+                        return Ok(None);
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
 
-    /// Try to read the next Event in the next number of milliseconds. 
-    pub fn read_timeout(&self, timeout: i32) -> QKResult<Event> {
-        let mut buf = [0u8; 10];
-        match self.device.read_timeout(&mut buf[..], timeout) {
-            Ok(_) => Ok(process_input(&buf)),
-            Err(e) => Err(QKError::QKHidError(e)),
+    /// Read the next input report as is, without decoding it nor filtering out the classes
+    /// of events we are not subscribed to.  Blocks unless set_blocking_mode(false).
+    ///
+    /// Returns the bytes actually read, or `None` if no report was available.
+    pub fn read_raw(&self) -> QKResult<Option<Vec<u8>>> {
+        if let Some(report) = self.pending.borrow_mut().pop_front() {
+            return Ok(Some(report.bytes().to_vec()));
         }
+        let mut buf = [0u8; INPUT_REPORT_SIZE];
+        let len = self.device.read(&mut buf[..])?;
+        Ok((len > 0).then(|| buf[..len].to_vec()))
+    }
+
+    /// Like `read_raw`, waiting at most the given number of milliseconds (-1 waits forever).
+    pub fn read_raw_timeout(&self, timeout: i32) -> QKResult<Option<Vec<u8>>> {
+        if let Some(report) = self.pending.borrow_mut().pop_front() {
+            return Ok(Some(report.bytes().to_vec()));
+        }
+        let mut buf = [0u8; INPUT_REPORT_SIZE];
+        let len = self.device.read_timeout(&mut buf[..], timeout)?;
+        Ok((len > 0).then(|| buf[..len].to_vec()))
+    }
+
+    /// Iterate over the events of the device, blocking until each one arrives.
+    ///
+    /// When the device is unplugged the iterator yields `Event::Disconnected` and ends.
+    pub fn events(&self) -> Events<'_> {
+        Events {
+            device: self,
+            done: false,
+        }
+    }
+
+    /// Next report received while waiting for something else (see `query_battery`).
+    fn pop_pending(&self) -> Option<InputReport> {
+        let mut pending = self.pending.borrow_mut();
+        while let Some(report) = pending.pop_front() {
+            if self.is_wanted(&report.event) {
+                return Some(report);
+            }
+        }
+        None
+    }
+
+    /// Ask the device for its battery level and wait up to `timeout` milliseconds for the answer
+    /// (-1 waits forever, like `read_timeout`).
+    ///
+    /// Returns `None` if the device didn't answer in time.  Any other event received while
+    /// waiting is kept and returned by the following calls to `read` and `read_timeout`.
+    ///
+    /// The device only reports its battery level when subscribing to battery events, so
+    /// this subscribes to them (see `subscriptions`) and the following battery reports are
+    /// delivered by `read` too.
+    pub fn query_battery(&self, timeout: i32) -> QKResult<Option<u8>> {
+        self.subscribe(EventClass::Battery)?;
+        let deadline = (timeout >= 0).then(|| Instant::now() + Duration::from_millis(timeout as u64));
+        loop {
+            let remaining = deadline.map_or(-1, |d| {
+                d.saturating_duration_since(Instant::now()).as_millis() as i32
+            });
+            let mut buf = [0u8; INPUT_REPORT_SIZE];
+            let len = self.device.read_timeout(&mut buf[..], remaining)?;
+            if len > 0 {
+                match InputReport::new(buf, len) {
+                    InputReport {
+                        event: Event::Battery { percent },
+                        ..
+                    } => return Ok(Some(percent)),
+                    report => self.pending.borrow_mut().push_back(report),
+                }
+            }
+            if matches!(deadline, Some(d) if Instant::now() >= d) {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/// Blocking iterator over the events of a device (see `QKDevice::events`).
+pub struct Events<'a> {
+    device: &'a QKDevice,
+    done: bool,
+}
+
+impl Iterator for Events<'_> {
+    type Item = QKResult<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.device.read_timeout(-1) {
+                Ok(Some(event)) => return Some(Ok(event)),
+                Ok(None) => continue,
+                Err(QKError::QKDisconnected) => {
+                    self.done = true;
+                    return Some(Ok(Event::Disconnected));
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests_device {
+    use super::*;
+    use msgs::*;
+    use record::testing::{input, open, replay};
+
+    #[test]
+    fn it_should_send_raw_frames() {
+        let (dev, replayer) = replay(&[]);
+        let frame = msgs::pad_zeroes([0x02, 0xb1, 0x0a, 0x01, 0x03]);
+        dev.send_raw(&frame).unwrap();
+        assert_eq!(replayer.written(), vec![frame.to_vec()]);
+    }
+
+    #[test]
+    fn it_should_track_raw_subscriptions() {
+        let (dev, _) = replay(&[]);
+        assert!(!dev.subscriptions().contains(EventClass::Battery));
+        dev.send_raw(&msg_subscribe_to_battery()).unwrap();
+        assert!(dev.subscriptions().contains(EventClass::Battery));
+        assert!(!dev.subscriptions().contains(EventClass::Keys));
+    }
+
+    #[test]
+    fn it_should_remember_what_was_set() {
+        let (dev, _) = replay(&[]);
+        assert_eq!(dev.ring_color(), None);
+        dev.set_ring_color(Color::RED).unwrap();
+        dev.set_key_text(1, "undo").unwrap();
+        dev.send_raw(&msg_set_screen_brightness(ScreenBrightness::Low))
+            .unwrap();
+        assert_eq!(dev.ring_color(), Some(Color::RED));
+        assert_eq!(dev.key_text(1).as_deref(), Some("undo"));
+        assert_eq!(dev.key_text(2), None);
+        assert_eq!(dev.screen_brightness(), Some(ScreenBrightness::Low));
+        assert_eq!(dev.snapshot().brightness, Some(ScreenBrightness::Low));
+    }
+
+    #[test]
+    fn it_should_remember_raw_overlays() {
+        let (dev, _) = replay(&[]);
+        for frame in msgs_show_overlay_text(5, "Disco, disco!") {
+            dev.send_raw(&frame).unwrap();
+        }
+        assert_eq!(dev.overlay().unwrap().text, "Disco, disco!");
+    }
+
+    #[test]
+    fn it_should_apply_only_what_changed() {
+        let (dev, replayer) = replay(&[]);
+        dev.set_ring_color(Color::RED).unwrap();
+        let mut config = DeviceConfig {
+            ring_color: Some(Color::RED),
+            sleep_timeout: Some(10),
+            ..DeviceConfig::default()
+        };
+        config.key_texts[4] = Some("mute".to_string());
+        let report = dev.apply(&config);
+        assert!(report.is_ok());
+        assert_eq!(
+            report.applied,
+            vec![ConfigField::SleepTimeout, ConfigField::KeyText(4)]
+        );
+        assert_eq!(report.skipped, vec![ConfigField::RingColor]);
+        assert_eq!(replayer.written().len(), 3);
+        assert_eq!(dev.apply(&config).applied, vec![]);
+    }
+
+    #[test]
+    fn it_should_send_only_changed_labels() {
+        let (dev, replayer) = replay(&[]);
+        let labels = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        assert_eq!(dev.set_all_key_texts(labels).unwrap(), 8);
+        let mut changed = labels;
+        changed[5] = "z";
+        assert_eq!(dev.set_all_key_texts(changed).unwrap(), 1);
+        assert_eq!(
+            replayer.written().last(),
+            Some(&msg_set_key_text(5, "z").to_vec())
+        );
+        assert_eq!(dev.set_all_key_texts(changed).unwrap(), 0);
+        assert_eq!(dev.refresh_all_key_texts(changed).unwrap(), 8);
+        assert_eq!(replayer.written().len(), 17);
+    }
+
+    #[test]
+    fn it_should_refuse_invalid_commands() {
+        let (dev, replayer) = replay(&[]);
+        assert!(matches!(
+            dev.set_key_text(8, "nope"),
+            Err(QKError::QKInvalidCommand(_))
+        ));
+        assert!(dev.show_overlay_text(&"x".repeat(33), 1).is_err());
+        assert!(replayer.written().is_empty());
+        assert_eq!(dev.overlay(), None);
+    }
+
+    #[test]
+    fn it_should_subscribe_when_querying_the_battery() {
+        let (dev, _) = replay(&[Event::Battery { percent: 42 }]);
+        dev.unsubscribe(EventClass::Battery);
+        assert_eq!(dev.query_battery(100).unwrap(), Some(42));
+        assert!(dev.subscriptions().contains(EventClass::Battery));
+    }
+
+    #[test]
+    fn it_should_wait_forever_for_the_battery_with_a_negative_timeout() {
+        let late = Record {
+            at: Duration::from_millis(200),
+            ..input(&Event::Battery { percent: 42 })
+        };
+        let (dev, _) = open(
+            Replayer::new(vec![late]).realtime(true),
+            Subscriptions::none(),
+        );
+        assert_eq!(dev.query_battery(-1).unwrap(), Some(42));
+    }
+
+    #[test]
+    fn it_should_parse_connection_modes() {
+        assert_eq!("auto".parse::<ConnectionMode>().unwrap(), ConnectionMode::Auto);
+        assert_eq!("wired".parse::<ConnectionMode>().unwrap(), ConnectionMode::Wired);
+        assert_eq!(
+            "wireless".parse::<ConnectionMode>().unwrap(),
+            ConnectionMode::Wireless
+        );
+        assert!(matches!(
+            "usb".parse::<ConnectionMode>(),
+            Err(QKError::QKParseError(_))
+        ));
+    }
+
+    #[test]
+    fn it_should_read_raw_reports_unfiltered() {
+        let battery = Event::Battery { percent: 42 };
+        let (dev, _) = replay(&[battery]);
+        assert_eq!(dev.read_raw().unwrap(), Some(encode_event(&battery).to_vec()));
+        assert!(matches!(dev.read_raw(), Err(QKError::QKDisconnected)));
     }
 }
@@ -1,5 +1,5 @@
 /// Pad the rightmost part of some array with zeroes up to given length.
-fn pad_zeroes<const A: usize, const B: usize>(arr: [u8; A]) -> [u8; B] {
+pub(crate) fn pad_zeroes<const A: usize, const B: usize>(arr: [u8; A]) -> [u8; B] {
     assert!(B >= A);
     let mut b = [0; B];
     b[..A].copy_from_slice(&arr);
@@ -43,6 +43,24 @@ pub fn msg_subscribe_to_battery() -> [u8; 32] {
     pad_zeroes([0x02, 0xb4, 0x10])
 }
 
+/// Classes of events the device can be subscribed to
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EventClass {
+    /// Button and wheel events
+    Keys,
+    /// Battery level reports
+    Battery,
+}
+
+/// A message to subscribe to the given class of events
+pub fn msg_subscribe(class: EventClass) -> [u8; 32] {
+    match class {
+        EventClass::Keys => msg_subscribe_to_key_events(),
+        EventClass::Battery => msg_subscribe_to_battery(),
+    }
+}
+
 /// Possible device screen orientations
 #[derive(Debug, PartialEq, Clone, Copy, Default)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
@@ -54,6 +72,20 @@ pub enum ScreenOrientation {
     Rotate270 = 4,
 }
 
+impl TryFrom<u8> for ScreenOrientation {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(ScreenOrientation::Rotate0),
+            2 => Ok(ScreenOrientation::Rotate90),
+            3 => Ok(ScreenOrientation::Rotate180),
+            4 => Ok(ScreenOrientation::Rotate270),
+            _ => Err(value),
+        }
+    }
+}
+
 /// A message to rotate the screen
 pub fn msg_rotate_screen(rot: ScreenOrientation) -> [u8; 32] {
     pad_zeroes([0x02, 0xb1, rot as u8])
@@ -70,6 +102,20 @@ pub enum ScreenBrightness {
     Full = 3,
 }
 
+impl TryFrom<u8> for ScreenBrightness {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ScreenBrightness::Off),
+            1 => Ok(ScreenBrightness::Low),
+            2 => Ok(ScreenBrightness::Medium),
+            3 => Ok(ScreenBrightness::Full),
+            _ => Err(value),
+        }
+    }
+}
+
 /// A message to change the screen brightness level
 pub fn msg_set_screen_brightness(level: ScreenBrightness) -> [u8; 32] {
     pad_zeroes([0x02, 0xb1, 0x0a, 0x01, level as u8])
@@ -87,6 +133,21 @@ pub enum WheelSpeed {
     Fastest = 1,
 }
 
+impl TryFrom<u8> for WheelSpeed {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            5 => Ok(WheelSpeed::Slowest),
+            4 => Ok(WheelSpeed::Slower),
+            3 => Ok(WheelSpeed::Normal),
+            2 => Ok(WheelSpeed::Faster),
+            1 => Ok(WheelSpeed::Fastest),
+            _ => Err(value),
+        }
+    }
+}
+
 /// A message to change the wheel speed
 pub fn msg_set_wheel_speed(speed: WheelSpeed) -> [u8; 32] {
     pad_zeroes([0x02, 0xb4, 0x04, 0x01, 0x01, speed as u8])
@@ -146,7 +207,7 @@ fn create_overlay_chunk(special_byte: u8, duration: u8, text: &str, has_more: bo
 }
 
 /// Part of a message sequence to show a text overlay
-fn submsg_overlay_chunk(is_cont: bool, duration: u8, text: &str, has_more: bool) -> [u8; 32] {
+pub(crate) fn submsg_overlay_chunk(is_cont: bool, duration: u8, text: &str, has_more: bool) -> [u8; 32] {
     let mut body = [0u8; 32];
     body[..7].clone_from_slice(&[
         0x02,
@@ -168,7 +229,11 @@ fn submsg_overlay_chunk(is_cont: bool, duration: u8, text: &str, has_more: bool)
 }
 
 /// A message sequence to show a text overlay
+///
+/// Panics if the text is longer than 32 bytes (`Command::ShowOverlayText` checks it).
 // TODO: consider unicode problems
+// `is_multiple_of` is too recent for the toolchains we support
+#[allow(unknown_lints, clippy::manual_is_multiple_of)]
 pub fn msgs_show_overlay_text(duration: u8, text: &str) -> Vec<[u8; 32]> {
     assert!(text.len() <= 32);
     let mut res = Vec::new();
@@ -184,14 +249,15 @@ pub fn msgs_show_overlay_text(duration: u8, text: &str) -> Vec<[u8; 32]> {
             (
                 i,
                 w,
-                i == ((text.len() / 8) - if (text.len() % 8) == 0 { 2 } else { 1 }),
+                // Short texts fit in a single frame (and would underflow here)
+                Some(i) == (text.len() / 8).checked_sub(if text.len() % 8 == 0 { 2 } else { 1 }),
             )
         })
     {
         res.push(submsg_overlay_chunk(
             i != 0,
             duration,
-            &chunk,
+            chunk,
             i==1 && text.len() > 16 || i > 0 && has_more,
         ))
     }
@@ -203,6 +269,15 @@ pub fn msgs_show_overlay_text(duration: u8, text: &str) -> Vec<[u8; 32]> {
 #[cfg(test)]
 mod tests_output_msgs {
     use super::*;
+    use crate::command::decode_command;
+
+    /// Decoding a frame and encoding it back must give the very same frame
+    fn assert_roundtrip(frames: &[[u8; 32]]) {
+        for frame in frames {
+            let decoded = decode_command(frame).expect("frame should be decodable");
+            assert_eq!(decoded.encode().unwrap(), vec![*frame]);
+        }
+    }
 
     #[test]
     fn it_should_match_subscribe_to_events() {
@@ -213,7 +288,8 @@ mod tests_output_msgs {
                 2, 176, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
                 0, 0, 0, 0, 0
             ]
-        )
+        );
+        assert_roundtrip(&[result]);
     }
 
     #[test]
@@ -225,7 +301,14 @@ mod tests_output_msgs {
                 2, 180, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
                 0, 0, 0, 0, 0
             ]
-        )
+        );
+        assert_roundtrip(&[result]);
+    }
+
+    #[test]
+    fn it_should_match_subscribe_by_class() {
+        assert_eq!(msg_subscribe(EventClass::Keys), msg_subscribe_to_key_events());
+        assert_eq!(msg_subscribe(EventClass::Battery), msg_subscribe_to_battery());
     }
 
     #[test]
@@ -237,7 +320,8 @@ mod tests_output_msgs {
                 2, 177, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
                 0, 0, 0, 0, 0
             ]
-        )
+        );
+        assert_roundtrip(&[result]);
     }
 
     #[test]
@@ -249,7 +333,8 @@ mod tests_output_msgs {
                 2, 177, 10, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
                 0, 0, 0, 0, 0
             ]
-        )
+        );
+        assert_roundtrip(&[result]);
     }
 
     #[test]
@@ -261,7 +346,8 @@ mod tests_output_msgs {
                 2, 180, 4, 1, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
                 0, 0, 0, 0, 0
             ]
-        )
+        );
+        assert_roundtrip(&[result]);
     }
 
     // TODO: Extract from api
@@ -280,7 +366,8 @@ mod tests_output_msgs {
                 2, 180, 1, 1, 0, 0, 1, 2, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
                 0, 0, 0, 0, 0
             ]
-        )
+        );
+        assert_roundtrip(&[result]);
     }
 
     #[test]
@@ -292,7 +379,8 @@ mod tests_output_msgs {
                 2, 177, 0, 4, 0, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 98, 0, 97, 0, 97, 0, 122, 0,
                 113, 0, 117, 0, 117, 0, 120, 0
             ]
-        )
+        );
+        assert_roundtrip(&[result]);
     }
 
     #[test]
@@ -314,7 +402,8 @@ mod tests_output_msgs {
                     0, 61, 0, 48, 0, 61, 0, 62, 0
                 ],
             ]
-        )
+        );
+        assert_roundtrip(&result);
     }
 
     #[test]
@@ -336,7 +425,8 @@ mod tests_output_msgs {
                     0, 0, 0, 0, 0, 0, 0, 0
                 ],
             ]
-        )
+        );
+        assert_roundtrip(&result);
     }
 
     #[test]
@@ -354,7 +444,8 @@ mod tests_output_msgs {
                     0, 33, 0, 0, 0, 0, 0, 0, 0
                 ],
             ]
-        )
+        );
+        assert_roundtrip(&result);
     }
 
     #[test]
@@ -368,7 +459,16 @@ mod tests_output_msgs {
                 [ 2, 177, 6, 2, 0, 16, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 47, 0, 47, 0, 47, 0, 47, 0, 47, 0, 47, 0, 47, 0, 47, 0 ],
                 [ 2, 177, 6, 2, 0, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 47, 0, 47, 0, 47, 0, 47, 0, 47, 0, 47, 0, 47, 0, 93, 0 ],
             ]
-        )
+        );
+        assert_roundtrip(&result);
+    }
+
+    #[test]
+    fn it_should_show_short_overlays_in_a_single_frame() {
+        for text in ["b", "8 chars!"] {
+            let result = msgs_show_overlay_text(1, text);
+            assert_eq!(result, vec![submsg_overlay_chunk(false, 1, text, false)]);
+        }
     }
 }
 
@@ -401,47 +501,209 @@ pub struct ButtonState {
     pub button_wheel: bool,
 }
 
+/// One of the buttons of the device
+///
+/// Serialized as the number of the key (0-7), `"extra"` or `"wheel"`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Key {
+    /// One of the eight keys with a label (0-7)
+    Button(u8),
+    /// The button above the wheel
+    Extra,
+    /// The button in the middle of the wheel
+    Wheel,
+}
+
+impl Key {
+    /// All the buttons of the device.
+    pub const ALL: [Key; 10] = [
+        Key::Button(0),
+        Key::Button(1),
+        Key::Button(2),
+        Key::Button(3),
+        Key::Button(4),
+        Key::Button(5),
+        Key::Button(6),
+        Key::Button(7),
+        Key::Extra,
+        Key::Wheel,
+    ];
+}
+
+impl std::str::FromStr for Key {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "extra" => Ok(Key::Extra),
+            "wheel" => Ok(Key::Wheel),
+            n => match n.parse::<u8>() {
+                Ok(k) if k < 8 => Ok(Key::Button(k)),
+                _ => Err(format!(
+                    "invalid key {:?}, expected 0-7, \"extra\" or \"wheel\"",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Key {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Key::Button(k) => serializer.serialize_u8(*k),
+            Key::Extra => serializer.serialize_str("extra"),
+            Key::Wheel => serializer.serialize_str("wheel"),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Key {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KeyVisitor;
+
+        impl serde::de::Visitor<'_> for KeyVisitor {
+            type Value = Key;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a key number (0-7), \"extra\" or \"wheel\"")
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Key, E> {
+                self.visit_str(&v.to_string())
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<Key, E> {
+                self.visit_str(&v.to_string())
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Key, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(KeyVisitor)
+    }
+}
+
+impl ButtonState {
+    /// Whether the given button is pressed.
+    pub fn is_pressed(&self, key: Key) -> bool {
+        match key {
+            Key::Button(0) => self.button_0,
+            Key::Button(1) => self.button_1,
+            Key::Button(2) => self.button_2,
+            Key::Button(3) => self.button_3,
+            Key::Button(4) => self.button_4,
+            Key::Button(5) => self.button_5,
+            Key::Button(6) => self.button_6,
+            Key::Button(7) => self.button_7,
+            Key::Button(_) => false,
+            Key::Extra => self.button_extra,
+            Key::Wheel => self.button_wheel,
+        }
+    }
+
+    /// The buttons pressed in this state that were not pressed in `previous`.
+    pub fn pressed_since(&self, previous: &ButtonState) -> Vec<Key> {
+        Key::ALL
+            .into_iter()
+            .filter(|&k| self.is_pressed(k) && !previous.is_pressed(k))
+            .collect()
+    }
+}
+
 /// Represent a state change of the device
 #[derive(Debug, PartialEq, Clone, Copy)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Event {
     Button { state: ButtonState },
-    Wheel { direction: WheelDirection },
+    /// The wheel moved, `state` holds the buttons being pressed meanwhile
+    Wheel {
+        direction: WheelDirection,
+        state: ButtonState,
+    },
     Battery { percent: u8 },
-    Unknown { data: [u8; 10] },
+    Unknown { data: [u8; INPUT_REPORT_SIZE] },
+    /// The device is gone (only produced by `QKDevice::events`, never by `process_input`)
+    Disconnected,
+}
+
+impl Event {
+    /// The class of events this event belongs to (`None` for unknown events).
+    pub fn class(&self) -> Option<EventClass> {
+        match self {
+            Event::Button { .. } | Event::Wheel { .. } => Some(EventClass::Keys),
+            Event::Battery { .. } => Some(EventClass::Battery),
+            Event::Unknown { .. } | Event::Disconnected => None,
+        }
+    }
+}
+
+/// Size of the input reports sent by the device
+pub const INPUT_REPORT_SIZE: usize = 32;
+
+/// An input report received from the device, along with its decoded Event
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InputReport {
+    pub event: Event,
+    /// Raw bytes of the report (zero padded up to INPUT_REPORT_SIZE)
+    pub data: [u8; INPUT_REPORT_SIZE],
+    /// Number of bytes actually received
+    pub len: usize,
+}
+
+impl InputReport {
+    /// Decode a report, given the buffer it was read into and the number of bytes read.
+    pub fn new(data: [u8; INPUT_REPORT_SIZE], len: usize) -> Self {
+        InputReport {
+            event: process_input(&data),
+            data,
+            len: len.min(INPUT_REPORT_SIZE),
+        }
+    }
+
+    /// The bytes actually received.
+    pub fn bytes(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
 }
 
 /// Process an input message from the device and translates it to an Event
 /// For messages that are malformed or not yet understood it returns Unknown
-pub fn process_input(data: &[u8; 10]) -> Event {
+pub fn process_input(data: &[u8; INPUT_REPORT_SIZE]) -> Event {
     if data[0] == 0x02 {
         if data[1] == 0xf0 {
+            let keys1 = data[2];
+            let keys2 = data[3];
+            let state = ButtonState {
+                button_0: keys1 & (1 << 0) > 0,
+                button_1: keys1 & (1 << 1) > 0,
+                button_2: keys1 & (1 << 2) > 0,
+                button_3: keys1 & (1 << 3) > 0,
+                button_4: keys1 & (1 << 4) > 0,
+                button_5: keys1 & (1 << 5) > 0,
+                button_6: keys1 & (1 << 6) > 0,
+                button_7: keys1 & (1 << 7) > 0,
+                button_extra: keys2 & (1 << 0) > 0,
+                button_wheel: keys2 & (1 << 1) > 0,
+            };
             let wheel_byte = data[7];
             if wheel_byte & 0x01 > 0 {
                 Event::Wheel {
                     direction: WheelDirection::Right,
+                    state,
                 }
             } else if wheel_byte & 0x02 > 0 {
                 Event::Wheel {
                     direction: WheelDirection::Left,
+                    state,
                 }
             } else {
-                let keys1 = data[2];
-                let keys2 = data[3];
-                Event::Button {
-                    state: ButtonState {
-                        button_0: keys1 & (1 << 0) > 0,
-                        button_1: keys1 & (1 << 1) > 0,
-                        button_2: keys1 & (1 << 2) > 0,
-                        button_3: keys1 & (1 << 3) > 0,
-                        button_4: keys1 & (1 << 4) > 0,
-                        button_5: keys1 & (1 << 5) > 0,
-                        button_6: keys1 & (1 << 6) > 0,
-                        button_7: keys1 & (1 << 7) > 0,
-                        button_extra: keys2 & (1 << 0) > 0,
-                        button_wheel: keys2 & (1 << 1) > 0,
-                    },
-                }
+                Event::Button { state }
             }
         } else if data[1] == 0xf2 && data[2] == 0x01 {
             Event::Battery { percent: data[3] }
@@ -453,17 +715,96 @@ pub fn process_input(data: &[u8; 10]) -> Event {
     }
 }
 
+/// Encode an Event as the input report the device would send (the inverse of `process_input`).
+///
+/// `Event::Disconnected` is not sent by the device, it is encoded as an empty report.
+pub fn encode_event(event: &Event) -> [u8; INPUT_REPORT_SIZE] {
+    let keys = |state: &ButtonState| {
+        let keys1 = [
+            state.button_0,
+            state.button_1,
+            state.button_2,
+            state.button_3,
+            state.button_4,
+            state.button_5,
+            state.button_6,
+            state.button_7,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0u8, |acc, (i, &pressed)| acc | ((pressed as u8) << i));
+        let keys2 = state.button_extra as u8 | (state.button_wheel as u8) << 1;
+        (keys1, keys2)
+    };
+    match event {
+        Event::Button { state } => {
+            let (keys1, keys2) = keys(state);
+            pad_zeroes([0x02, 0xf0, keys1, keys2])
+        }
+        Event::Wheel { direction, state } => {
+            let (keys1, keys2) = keys(state);
+            let wheel_byte = match direction {
+                WheelDirection::Right => 0x01,
+                WheelDirection::Left => 0x02,
+            };
+            pad_zeroes([0x02, 0xf0, keys1, keys2, 0, 0, 0, wheel_byte])
+        }
+        Event::Battery { percent } => pad_zeroes([0x02, 0xf2, 0x01, *percent]),
+        Event::Unknown { data } => *data,
+        Event::Disconnected => [0u8; INPUT_REPORT_SIZE],
+    }
+}
+
 #[cfg(test)]
 mod tests_input_msgs {
     use super::*;
 
+    #[test]
+    fn it_should_detect_new_presses() {
+        let previous = ButtonState {
+            button_1: true,
+            ..ButtonState::default()
+        };
+        let state = ButtonState {
+            button_1: true,
+            button_6: true,
+            button_extra: true,
+            ..ButtonState::default()
+        };
+        assert!(state.is_pressed(Key::Button(6)));
+        assert!(!state.is_pressed(Key::Wheel));
+        assert!(!state.is_pressed(Key::Button(8)));
+        assert_eq!(
+            state.pressed_since(&previous),
+            vec![Key::Button(6), Key::Extra]
+        );
+    }
+
+    #[test]
+    fn it_should_keep_the_whole_report() {
+        let mut data = [0u8; INPUT_REPORT_SIZE];
+        data[..3].copy_from_slice(&[2, 250, 1]);
+        data[31] = 42;
+        let report = InputReport::new(data, INPUT_REPORT_SIZE);
+        assert_eq!(report.event, Event::Unknown { data });
+        assert_eq!(report.bytes()[31], 42);
+        assert_eq!(InputReport::new(data, 3).bytes(), &[2, 250, 1]);
+    }
+
+    #[test]
+    fn it_should_decode_battery() {
+        let result = process_input(&pad_zeroes([2, 242, 1, 73]));
+        assert_eq!(result, Event::Battery { percent: 73 })
+    }
+
     #[test]
     fn it_should_decode_wheel_left() {
         let result = process_input(&pad_zeroes([2, 240, 0, 0, 0, 0, 0, 2, 0, 0]));
         assert_eq!(
             result,
             Event::Wheel {
-                direction: WheelDirection::Left
+                direction: WheelDirection::Left,
+                state: ButtonState::default(),
             }
         )
     }
@@ -474,7 +815,24 @@ mod tests_input_msgs {
         assert_eq!(
             result,
             Event::Wheel {
-                direction: WheelDirection::Right
+                direction: WheelDirection::Right,
+                state: ButtonState::default(),
+            }
+        )
+    }
+
+    #[test]
+    fn it_should_decode_wheel_while_pressing_buttons() {
+        let result = process_input(&pad_zeroes([2, 240, 4, 2, 0, 0, 0, 2, 0, 0]));
+        assert_eq!(
+            result,
+            Event::Wheel {
+                direction: WheelDirection::Left,
+                state: ButtonState {
+                    button_2: true,
+                    button_wheel: true,
+                    ..Default::default()
+                }
             }
         )
     }
@@ -721,3 +1079,65 @@ mod tests_input_msgs {
         )
     }
 }
+
+#[cfg(test)]
+mod tests_encode_event {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn button_state() -> impl Strategy<Value = ButtonState> {
+        prop::array::uniform10(any::<bool>()).prop_map(|b| ButtonState {
+            button_0: b[0],
+            button_1: b[1],
+            button_2: b[2],
+            button_3: b[3],
+            button_4: b[4],
+            button_5: b[5],
+            button_6: b[6],
+            button_7: b[7],
+            button_extra: b[8],
+            button_wheel: b[9],
+        })
+    }
+
+    fn event() -> impl Strategy<Value = Event> {
+        prop_oneof![
+            button_state().prop_map(|state| Event::Button { state }),
+            (any::<bool>(), button_state()).prop_map(|(left, state)| Event::Wheel {
+                direction: if left {
+                    WheelDirection::Left
+                } else {
+                    WheelDirection::Right
+                },
+                state,
+            }),
+            any::<u8>().prop_map(|percent| Event::Battery { percent }),
+            // Anything not starting with the report id 0x02 is not understood
+            any::<[u8; INPUT_REPORT_SIZE]>()
+                .prop_filter("known report id", |data| data[0] != 0x02)
+                .prop_map(|data| Event::Unknown { data }),
+        ]
+    }
+
+    #[test]
+    fn it_should_encode_what_the_device_sends() {
+        assert_eq!(
+            encode_event(&Event::Wheel {
+                direction: WheelDirection::Left,
+                state: ButtonState::default(),
+            }),
+            pad_zeroes([2, 240, 0, 0, 0, 0, 0, 2, 0, 0])
+        );
+        assert_eq!(
+            encode_event(&Event::Battery { percent: 73 }),
+            pad_zeroes([2, 242, 1, 73])
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn it_should_roundtrip_events(event in event()) {
+            prop_assert_eq!(process_input(&encode_event(&event)), event);
+        }
+    }
+}
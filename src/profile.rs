@@ -0,0 +1,428 @@
+//! Profile files: keypad layouts described in JSON, TOML (`toml` feature) or YAML
+//! (`yaml` feature).
+//!
+//! ```toml
+//! switch_key = "extra"   # key cycling the layers (0-7, "extra" or "wheel")
+//! overlay_seconds = 1    # how long the name of a new layer is shown
+//!
+//! [device]
+//! orientation = "Rotate90"
+//! brightness = "Medium"
+//! wheel_speed = "Normal"
+//! sleep_timeout = 30
+//!
+//! [[layers]]
+//! name = "Edit"
+//! ring_color = "#ff8800"
+//! labels = ["Copy", "Paste", "Undo"]
+//! keys = { 0 = "copy", 1 = "paste", 2 = "undo" }
+//! wheel = { left = "zoom-out", right = "zoom-in" }
+//! chords = [{ keys = [0, 1], action = "duplicate" }]
+//! ```
+//!
+//! Invalid files are rejected with an error pointing to the offending line.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::de::{Error, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+
+use crate::color::Color;
+use crate::config::DeviceConfig;
+use crate::error::QKError;
+use crate::layers::{Chord, Layer, Profile};
+use crate::msgs::{Key, ScreenBrightness, ScreenOrientation, WheelSpeed};
+use crate::QKResult;
+
+/// Maximum number of characters shown on a key label
+const MAX_LABEL_LEN: usize = 8;
+/// Maximum length in bytes of a layer name, shown as an overlay when switching to it
+const MAX_LAYER_NAME_LEN: usize = 32;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawProfile {
+    #[serde(default)]
+    device: DeviceSettings,
+    #[serde(default = "default_switch_key")]
+    switch_key: Key,
+    #[serde(default = "default_overlay_seconds")]
+    overlay_seconds: u8,
+    layers: Layers,
+}
+
+fn default_switch_key() -> Key {
+    Key::Extra
+}
+
+fn default_overlay_seconds() -> u8 {
+    1
+}
+
+/// The settings of a profile not depending on the layer
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct DeviceSettings {
+    orientation: Option<ScreenOrientation>,
+    brightness: Option<ScreenBrightness>,
+    wheel_speed: Option<WheelSpeed>,
+    sleep_timeout: Option<u8>,
+}
+
+/// At least one layer
+struct Layers(Vec<Layer>);
+
+impl<'de> Deserialize<'de> for Layers {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let layers = Vec::<Layer>::deserialize(deserializer)?;
+        if layers.is_empty() {
+            return Err(D::Error::custom("a profile needs at least one layer"));
+        }
+        Ok(Layers(layers))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawLayer {
+    name: LayerName,
+    #[serde(default)]
+    ring_color: Color,
+    #[serde(default)]
+    labels: Labels,
+    #[serde(default)]
+    keys: BTreeMap<ButtonIndex, String>,
+    #[serde(default)]
+    chords: Vec<Chord>,
+    #[serde(default)]
+    wheel: WheelBindings,
+}
+
+/// A layer name short enough to fit in an overlay
+struct LayerName(String);
+
+impl<'de> Deserialize<'de> for LayerName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Checked by the visitor too, for the error to point to the right line
+        struct LayerNameVisitor;
+
+        impl Visitor<'_> for LayerNameVisitor {
+            type Value = LayerName;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a layer name")
+            }
+
+            fn visit_str<E: Error>(self, name: &str) -> Result<LayerName, E> {
+                if name.len() > MAX_LAYER_NAME_LEN {
+                    return Err(E::custom(format!(
+                        "layer name {:?} is longer than {} bytes",
+                        name, MAX_LAYER_NAME_LEN
+                    )));
+                }
+                Ok(LayerName(name.to_string()))
+            }
+        }
+
+        deserializer.deserialize_str(LayerNameVisitor)
+    }
+}
+
+/// Up to eight labels of at most `MAX_LABEL_LEN` characters
+#[derive(Default)]
+struct Labels([String; 8]);
+
+impl<'de> Deserialize<'de> for Labels {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Labels are checked as they are read, for the error to point to the right line
+        struct LabelsVisitor;
+
+        impl<'de> Visitor<'de> for LabelsVisitor {
+            type Value = Labels;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a list of up to 8 labels")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Labels, A::Error> {
+                let mut labels = Labels::default();
+                let mut count = 0;
+                while let Some(label) = seq.next_element::<String>()? {
+                    if count == 8 {
+                        return Err(A::Error::custom("too many labels, the device has 8 keys"));
+                    }
+                    if label.chars().count() > MAX_LABEL_LEN {
+                        return Err(A::Error::custom(format!(
+                            "label {:?} is longer than {} characters",
+                            label, MAX_LABEL_LEN
+                        )));
+                    }
+                    labels.0[count] = label;
+                    count += 1;
+                }
+                Ok(labels)
+            }
+        }
+
+        deserializer.deserialize_seq(LabelsVisitor)
+    }
+}
+
+/// One of the eight keys with a label
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct ButtonIndex(u8);
+
+impl<'de> Deserialize<'de> for ButtonIndex {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match Key::deserialize(deserializer)? {
+            Key::Button(k) => Ok(ButtonIndex(k)),
+            key => Err(D::Error::custom(format!(
+                "{:?} can't be bound, use it in a chord or as the switch key",
+                key
+            ))),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct WheelBindings {
+    left: Option<String>,
+    right: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawChord {
+    keys: Vec<Key>,
+    action: String,
+}
+
+impl<'de> Deserialize<'de> for Chord {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let RawChord { keys, action } = RawChord::deserialize(deserializer)?;
+        if keys.len() < 2 {
+            return Err(D::Error::custom("a chord needs at least two keys"));
+        }
+        if keys.iter().enumerate().any(|(i, k)| keys[..i].contains(k)) {
+            return Err(D::Error::custom("the keys of a chord must be different"));
+        }
+        Ok(Chord { keys, action })
+    }
+}
+
+impl<'de> Deserialize<'de> for Layer {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = RawLayer::deserialize(deserializer)?;
+        let mut actions: [Option<String>; 8] = Default::default();
+        for (ButtonIndex(k), action) in raw.keys {
+            actions[k as usize] = Some(action);
+        }
+        Ok(Layer {
+            name: raw.name.0,
+            labels: raw.labels.0,
+            ring_color: raw.ring_color,
+            actions,
+            chords: raw.chords,
+            wheel_left: raw.wheel.left,
+            wheel_right: raw.wheel.right,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Profile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = RawProfile::deserialize(deserializer)?;
+        Ok(Profile {
+            device: DeviceConfig {
+                orientation: raw.device.orientation,
+                brightness: raw.device.brightness,
+                wheel_speed: raw.device.wheel_speed,
+                sleep_timeout: raw.device.sleep_timeout,
+                ..DeviceConfig::default()
+            },
+            layers: raw.layers.0,
+            switch_key: raw.switch_key,
+            overlay_seconds: raw.overlay_seconds,
+        })
+    }
+}
+
+impl Profile {
+    /// Read a profile in JSON.
+    pub fn from_json(s: &str) -> QKResult<Self> {
+        serde_json::from_str(s).map_err(|e| QKError::QKParseError(e.to_string()))
+    }
+
+    /// Read a profile in TOML.
+    #[cfg(feature = "toml")]
+    pub fn from_toml(s: &str) -> QKResult<Self> {
+        toml::from_str(s).map_err(|e| QKError::QKParseError(e.to_string()))
+    }
+
+    /// Read a profile in YAML.
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml(s: &str) -> QKResult<Self> {
+        serde_yaml::from_str(s).map_err(|e| QKError::QKParseError(e.to_string()))
+    }
+
+    /// Read a profile file, in the format given by its extension (`.json`, `.toml`,
+    /// `.yaml` or `.yml`).
+    pub fn load(path: impl AsRef<Path>) -> QKResult<Self> {
+        let path = path.as_ref();
+        let s = std::fs::read_to_string(path)?;
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        let profile = match extension {
+            "json" => Profile::from_json(&s),
+            #[cfg(feature = "toml")]
+            "toml" => Profile::from_toml(&s),
+            #[cfg(feature = "yaml")]
+            "yaml" | "yml" => Profile::from_yaml(&s),
+            _ => Err(QKError::QKParseError(format!(
+                "unsupported profile format {:?}",
+                extension
+            ))),
+        };
+        profile.map_err(|e| match e {
+            QKError::QKParseError(msg) => {
+                QKError::QKParseError(format!("{}: {}", path.display(), msg))
+            }
+            e => e,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests_profile {
+    use super::*;
+
+    const JSON: &str = r##"{
+        "switch_key": "wheel",
+        "device": { "brightness": "Full", "sleep_timeout": 30 },
+        "layers": [
+            {
+                "name": "Edit",
+                "ring_color": "orange",
+                "labels": ["Copy", "Paste"],
+                "keys": { "0": "copy", "1": "paste" },
+                "wheel": { "left": "zoom-out" },
+                "chords": [{ "keys": [0, "extra"], "action": "duplicate" }]
+            },
+            { "name": "Empty" }
+        ]
+    }"##;
+
+    #[test]
+    fn it_should_read_json_profiles() {
+        let profile = Profile::from_json(JSON).unwrap();
+        assert_eq!(profile.switch_key, Key::Wheel);
+        assert_eq!(profile.overlay_seconds, 1);
+        assert_eq!(profile.device.brightness, Some(ScreenBrightness::Full));
+        assert_eq!(profile.device.sleep_timeout, Some(30));
+        assert_eq!(profile.layers.len(), 2);
+        let edit = &profile.layers[0];
+        assert_eq!(edit.ring_color, Color::rgb(255, 165, 0));
+        assert_eq!(edit.labels[1], "Paste");
+        assert_eq!(edit.labels[2], "");
+        assert_eq!(edit.actions[0].as_deref(), Some("copy"));
+        assert_eq!(edit.actions[2], None);
+        assert_eq!(edit.wheel_left.as_deref(), Some("zoom-out"));
+        assert_eq!(edit.chords[0].keys, vec![Key::Button(0), Key::Extra]);
+        assert_eq!(
+            profile.layers[1],
+            Layer {
+                name: "Empty".to_string(),
+                ..Layer::default()
+            }
+        );
+    }
+
+    fn error(json: &str) -> String {
+        match Profile::from_json(json) {
+            Err(QKError::QKParseError(msg)) => msg,
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn it_should_point_to_the_offending_line() {
+        let msg = error(
+            "{\n  \"layers\": [{\n    \"name\": \"x\",\n    \"keys\": { \"9\": \"nope\" }\n  }]\n}",
+        );
+        assert!(msg.contains("invalid key"), "{}", msg);
+        assert!(msg.contains("line 4"), "{}", msg);
+        let msg = error("{\n  \"layers\": [{\n    \"name\": \"x\",\n    \"labels\": [\"much too long\"]\n  }]\n}");
+        assert!(msg.contains("longer than 8"), "{}", msg);
+        assert!(msg.contains("line 4"), "{}", msg);
+        let msg = error(&format!(
+            "{{\n  \"layers\": [{{\n    \"name\": \"{}\"\n  }}]\n}}",
+            "x".repeat(33)
+        ));
+        assert!(msg.contains("longer than 32 bytes"), "{}", msg);
+        assert!(msg.contains("line 3"), "{}", msg);
+    }
+
+    #[test]
+    fn it_should_validate_profiles() {
+        assert!(error(r#"{"layers": []}"#).contains("at least one layer"));
+        assert!(
+            error(r#"{"layers": [{"name": "x", "chords": [{"keys": [1], "action": "a"}]}]}"#)
+                .contains("at least two keys")
+        );
+        assert!(
+            error(r#"{"layers": [{"name": "x", "keys": {"extra": "a"}}]}"#)
+                .contains("can't be bound")
+        );
+        assert!(error(r#"{"layers": [{"name": "x", "colour": "red"}]}"#).contains("unknown field"));
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn it_should_read_toml_profiles() {
+        let profile = Profile::from_toml(
+            r##"
+switch_key = 7
+
+[device]
+orientation = "Rotate90"
+
+[[layers]]
+name = "Edit"
+labels = ["Copy"]
+keys = { 0 = "copy" }
+"##,
+        )
+        .unwrap();
+        assert_eq!(profile.switch_key, Key::Button(7));
+        assert_eq!(
+            profile.device.orientation,
+            Some(ScreenOrientation::Rotate90)
+        );
+        assert_eq!(profile.layers[0].actions[0].as_deref(), Some("copy"));
+
+        let msg =
+            match Profile::from_toml("[[layers]]\nname = \"x\"\nlabels = [\"much too long\"]\n") {
+                Err(QKError::QKParseError(msg)) => msg,
+                _ => panic!("expected an error"),
+            };
+        assert!(msg.contains("line 3"), "{}", msg);
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn it_should_read_yaml_profiles() {
+        let profile = Profile::from_yaml(
+            "layers:\n  - name: Edit\n    keys:\n      3: save\n    wheel:\n      right: next\n",
+        )
+        .unwrap();
+        assert_eq!(profile.layers[0].actions[3].as_deref(), Some("save"));
+        assert_eq!(profile.layers[0].wheel_right.as_deref(), Some("next"));
+
+        let msg = match Profile::from_yaml("layers:\n  - name: Edit\n    keys:\n      8: save\n") {
+            Err(QKError::QKParseError(msg)) => msg,
+            _ => panic!("expected an error"),
+        };
+        assert!(msg.contains("line 4"), "{}", msg);
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::command::Command;
+use crate::error::QKError;
+use crate::msgs::{Event, EventClass};
+use crate::{QKDevice, QKResult};
+
+/// Default maximum number of reports per second written by an `OutputQueue`
+pub const DEFAULT_MAX_RATE: u32 = 50;
+
+/// What a command sets on the device: a newer command for the same slot supersedes it.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Slot {
+    Subscription(EventClass),
+    Orientation,
+    Brightness,
+    WheelSpeed,
+    SleepTimeout,
+    RingColor,
+    KeyText(u8),
+    Overlay,
+}
+
+impl Slot {
+    fn of(command: &Command) -> Self {
+        match command {
+            Command::Subscribe { class } => Slot::Subscription(*class),
+            Command::SetScreenOrientation { .. } => Slot::Orientation,
+            Command::SetScreenBrightness { .. } => Slot::Brightness,
+            Command::SetWheelSpeed { .. } => Slot::WheelSpeed,
+            Command::SetSleepTimeout { .. } => Slot::SleepTimeout,
+            Command::SetRingColor { .. } => Slot::RingColor,
+            Command::SetKeyText { key, .. } => Slot::KeyText(*key),
+            Command::ShowOverlayText { .. } => Slot::Overlay,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Queue {
+    pending: VecDeque<(Slot, Command)>,
+    /// A command is being written to the device
+    busy: bool,
+    closed: bool,
+    error: Option<QKError>,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Send commands to a device from a background thread, without flooding it.
+///
+/// A command waiting in the queue is replaced by any newer command setting the same thing
+/// (the ring color, the label of a given key, the overlay...), and reports are written at
+/// most at the configured rate.  Dropping the queue writes whatever is still pending.
+///
+/// The device is shared with the writing thread, so it must not be kept locked while
+/// waiting for events (e.g. in a blocking `QKDevice::read`): read them with `read_timeout`,
+/// which gives way to the commands being written.
+///
+/// ```no_run
+/// # use std::sync::{Arc, Mutex};
+/// # use xencelabs_quick_keys::*;
+/// # fn main() -> QKResult<()> {
+/// let dev = QKDevice::open(hidapi::HidApi::new()?, ConnectionMode::Auto)?;
+/// let queue = OutputQueue::new(Arc::new(Mutex::new(dev)));
+/// for hue in 0..360 {
+///     queue.push(Command::SetRingColor {
+///         color: Color::from_hsv(hue as f32, 1.0, 1.0),
+///     });
+/// }
+/// queue.flush();
+/// while let Some(event) = queue.read_timeout(100)? {
+///     println!("{:?}", event);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct OutputQueue {
+    device: Arc<Mutex<QKDevice>>,
+    shared: Arc<Shared>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl OutputQueue {
+    /// Queue commands for the given device, writing at most `DEFAULT_MAX_RATE` reports per second.
+    pub fn new(device: Arc<Mutex<QKDevice>>) -> Self {
+        OutputQueue::with_max_rate(device, DEFAULT_MAX_RATE)
+    }
+
+    /// Queue commands for the given device, writing at most `reports_per_second` reports
+    /// per second (0 means no limit).
+    pub fn with_max_rate(device: Arc<Mutex<QKDevice>>, reports_per_second: u32) -> Self {
+        let interval = match reports_per_second {
+            0 => Duration::ZERO,
+            rate => Duration::from_secs(1) / rate,
+        };
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue::default()),
+            changed: Condvar::new(),
+        });
+        let worker = {
+            let device = Arc::clone(&device);
+            let shared = Arc::clone(&shared);
+            std::thread::spawn(move || run(device, shared, interval))
+        };
+        OutputQueue {
+            device,
+            shared,
+            worker: Some(worker),
+        }
+    }
+
+    /// Queue a command, replacing any pending command setting the same thing.
+    pub fn push(&self, command: Command) {
+        let slot = Slot::of(&command);
+        let mut queue = self.shared.lock();
+        match queue.pending.iter_mut().find(|(s, _)| *s == slot) {
+            Some(pending) => pending.1 = command,
+            None => queue.pending.push_back((slot, command)),
+        }
+        self.shared.changed.notify_all();
+    }
+
+    /// Number of commands waiting to be written.
+    pub fn len(&self) -> usize {
+        self.shared.lock().pending.len()
+    }
+
+    /// Whether every queued command has been written.
+    pub fn is_empty(&self) -> bool {
+        let queue = self.shared.lock();
+        queue.pending.is_empty() && !queue.busy
+    }
+
+    /// Block until every queued command has been written.
+    pub fn flush(&self) {
+        let mut queue = self.shared.lock();
+        while !queue.pending.is_empty() || queue.busy {
+            queue = self
+                .shared
+                .changed
+                .wait(queue)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// The last error returned by the device while writing, if any (the command is dropped).
+    pub fn take_error(&self) -> Option<QKError> {
+        self.shared.lock().error.take()
+    }
+
+    /// Read the next event of the device, waiting at most `timeout` milliseconds (see
+    /// `QKDevice::read_timeout`).
+    ///
+    /// The device is not locked while a command is being written, and stays locked while
+    /// waiting, so keep the timeout short (not -1) for the queue to keep writing.
+    pub fn read_timeout(&self, timeout: i32) -> QKResult<Option<Event>> {
+        let mut queue = self.shared.lock();
+        while queue.busy {
+            queue = self
+                .shared
+                .changed
+                .wait(queue)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        drop(queue);
+        self.device
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .read_timeout(timeout)
+    }
+
+    /// The device the commands are written to.
+    pub fn device(&self) -> &Arc<Mutex<QKDevice>> {
+        &self.device
+    }
+}
+
+impl Drop for OutputQueue {
+    fn drop(&mut self) {
+        self.shared.lock().closed = true;
+        self.shared.changed.notify_all();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Write the queued commands until the queue is closed and empty.
+fn run(device: Arc<Mutex<QKDevice>>, shared: Arc<Shared>, interval: Duration) {
+    let mut next = Instant::now();
+    loop {
+        // Wait for the rate limit before picking a command, so that it can still be superseded
+        std::thread::sleep(next.saturating_duration_since(Instant::now()));
+        let command = {
+            let mut queue = shared.lock();
+            loop {
+                if let Some((_, command)) = queue.pending.pop_front() {
+                    queue.busy = true;
+                    break command;
+                }
+                if queue.closed {
+                    return;
+                }
+                queue = shared
+                    .changed
+                    .wait(queue)
+                    .unwrap_or_else(|e| e.into_inner());
+            }
+        };
+        let result = device
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .send(&command);
+        next = Instant::now() + interval * command.encode().map_or(1, |f| f.len()) as u32;
+        let mut queue = shared.lock();
+        queue.busy = false;
+        if let Err(e) = result {
+            queue.error = Some(e);
+        }
+        shared.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests_queue {
+    use super::*;
+    use crate::color::Color;
+    use crate::msgs::msg_set_wheel_color;
+    use crate::record::testing::{input, open, replay};
+    use crate::record::{Record, Replayer};
+    use crate::Subscriptions;
+
+    fn device() -> (Arc<Mutex<QKDevice>>, Arc<Replayer>) {
+        let (dev, replayer) = replay(&[]);
+        (Arc::new(Mutex::new(dev)), replayer)
+    }
+
+    #[test]
+    fn it_should_coalesce_ring_colors() {
+        let (dev, replayer) = device();
+        let queue = OutputQueue::with_max_rate(dev, 10);
+        for blue in 0..100 {
+            queue.push(Command::SetRingColor {
+                color: Color::rgb(0, 0, blue),
+            });
+        }
+        queue.flush();
+        let written = replayer.written();
+        assert!(written.len() < 10);
+        assert_eq!(
+            written.last(),
+            Some(&msg_set_wheel_color(0, 0, 99).to_vec())
+        );
+    }
+
+    #[test]
+    fn it_should_coalesce_labels_per_key() {
+        let (dev, _) = device();
+        let queue = OutputQueue::with_max_rate(Arc::clone(&dev), 10);
+        let label = |key, text: &str| Command::SetKeyText {
+            key,
+            text: text.to_string(),
+        };
+        queue.push(label(0, "a"));
+        queue.push(label(1, "b"));
+        queue.push(label(0, "c"));
+        queue.flush();
+        let dev = dev.lock().unwrap();
+        assert_eq!(dev.key_text(0).as_deref(), Some("c"));
+        assert_eq!(dev.key_text(1).as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn it_should_flush_on_drop() {
+        let (dev, replayer) = device();
+        let queue = OutputQueue::with_max_rate(dev, 20);
+        queue.push(Command::SetSleepTimeout { minutes: 3 });
+        queue.push(Command::SetRingColor { color: Color::RED });
+        drop(queue);
+        assert_eq!(replayer.written().len(), 2);
+    }
+
+    #[test]
+    fn it_should_keep_writing_while_reading() {
+        let battery = Event::Battery { percent: 42 };
+        let (dev, replayer) = open(
+            Replayer::new(vec![Record {
+                at: Duration::from_millis(500),
+                ..input(&battery)
+            }])
+            .realtime(true),
+            Subscriptions::all(),
+        );
+        let queue = OutputQueue::with_max_rate(Arc::new(Mutex::new(dev)), 0);
+        std::thread::scope(|scope| {
+            let reader = scope.spawn(|| loop {
+                if let Some(event) = queue.read_timeout(20).unwrap() {
+                    return event;
+                }
+            });
+            std::thread::sleep(Duration::from_millis(50));
+            for key in 0..8 {
+                queue.push(Command::SetKeyText {
+                    key,
+                    text: key.to_string(),
+                });
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            queue.flush();
+            // The writes went through while the reader was still waiting for the battery
+            assert_eq!(replayer.remaining(), 1);
+            assert!(!reader.is_finished());
+            // After the two subscriptions
+            assert_eq!(replayer.written().len(), 10);
+            assert_eq!(reader.join().unwrap(), battery);
+        });
+    }
+}
@@ -0,0 +1,396 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use hidapi::{HidError, HidResult};
+
+use crate::error::QKError;
+use crate::transport::Transport;
+use crate::QKResult;
+
+/// Magic bytes at the beginning of every recording
+const MAGIC: &[u8; 4] = b"QKR\x01";
+
+/// Direction of a recorded frame
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Direction {
+    /// Written to the device
+    Out,
+    /// Read from the device
+    In,
+}
+
+/// A frame written to (or a report read from) the device during a recording.
+///
+/// Recordings are stored in a compact binary format: a 4 bytes header (`QKR\x01`) followed
+/// by one entry per record: direction (1 byte, 0 = out, 1 = in), time since the start of
+/// the recording (8 bytes, microseconds, little endian), length (2 bytes, little endian)
+/// and the bytes of the frame.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Record {
+    pub at: Duration,
+    pub direction: Direction,
+    pub data: Vec<u8>,
+}
+
+impl Record {
+    fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(&[match self.direction {
+            Direction::Out => 0,
+            Direction::In => 1,
+        }])?;
+        w.write_all(&(self.at.as_micros() as u64).to_le_bytes())?;
+        w.write_all(&(self.data.len() as u16).to_le_bytes())?;
+        w.write_all(&self.data)
+    }
+
+    fn read_from(r: &mut impl Read) -> QKResult<Option<Self>> {
+        let mut direction = [0u8; 1];
+        if r.read(&mut direction)? == 0 {
+            return Ok(None);
+        }
+        let direction = match direction[0] {
+            0 => Direction::Out,
+            1 => Direction::In,
+            d => {
+                return Err(QKError::QKParseError(format!(
+                    "invalid record direction {}",
+                    d
+                )))
+            }
+        };
+        let mut at = [0u8; 8];
+        r.read_exact(&mut at)?;
+        let mut len = [0u8; 2];
+        r.read_exact(&mut len)?;
+        let mut data = vec![0u8; u16::from_le_bytes(len) as usize];
+        r.read_exact(&mut data)?;
+        Ok(Some(Record {
+            at: Duration::from_micros(u64::from_le_bytes(at)),
+            direction,
+            data,
+        }))
+    }
+}
+
+/// Read all the records of a recording made with a `Recorder`.
+pub fn read_recording(reader: impl Read) -> QKResult<Vec<Record>> {
+    let mut reader = io::BufReader::new(reader);
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(QKError::QKParseError(
+            "not a Quick Keys recording".to_string(),
+        ));
+    }
+    let mut records = Vec::new();
+    while let Some(record) = Record::read_from(&mut reader)? {
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// A transport recording every frame written and every report read through it.
+///
+/// ```no_run
+/// # use xencelabs_quick_keys::*;
+/// # fn main() -> QKResult<()> {
+/// let options = OpenOptions::new().mode(ConnectionMode::Auto);
+/// let hid = options.connect(&hidapi::HidApi::new()?)?;
+/// let output = std::fs::File::create("session.qkr")?;
+/// let dev = options.open_transport(Recorder::new(hid, output)?)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Recorder<T, W> {
+    inner: T,
+    output: Mutex<W>,
+    start: Instant,
+}
+
+impl<T: Transport, W: Write + Send> Recorder<T, W> {
+    /// Record the traffic going through `inner` into `output`.
+    pub fn new(inner: T, mut output: W) -> QKResult<Self> {
+        output.write_all(MAGIC)?;
+        Ok(Recorder {
+            inner,
+            output: Mutex::new(output),
+            start: Instant::now(),
+        })
+    }
+
+    /// Stop recording, returning the recorded transport and the output.
+    pub fn into_inner(self) -> (T, W) {
+        let output = self.output.into_inner().unwrap_or_else(|e| e.into_inner());
+        (self.inner, output)
+    }
+
+    fn record(&self, direction: Direction, data: &[u8]) -> HidResult<()> {
+        let record = Record {
+            at: self.start.elapsed(),
+            direction,
+            data: data.to_vec(),
+        };
+        let mut output = self.output.lock().unwrap_or_else(|e| e.into_inner());
+        record
+            .write_to(&mut *output)
+            .and_then(|_| output.flush())
+            .map_err(|error| HidError::IoError { error })
+    }
+}
+
+impl<T: Transport, W: Write + Send> Transport for Recorder<T, W> {
+    fn write(&self, data: &[u8]) -> HidResult<usize> {
+        let written = self.inner.write(data)?;
+        self.record(Direction::Out, data)?;
+        Ok(written)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> HidResult<usize> {
+        let read = self.inner.read(buf)?;
+        if read > 0 {
+            self.record(Direction::In, &buf[..read])?;
+        }
+        Ok(read)
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> HidResult<usize> {
+        let read = self.inner.read_timeout(buf, timeout)?;
+        if read > 0 {
+            self.record(Direction::In, &buf[..read])?;
+        }
+        Ok(read)
+    }
+
+    fn set_blocking_mode(&self, blocking: bool) -> HidResult<()> {
+        self.inner.set_blocking_mode(blocking)
+    }
+}
+
+/// A fake device playing back the reports of a recording.
+///
+/// Reports are returned in order by the read methods (immediately, unless `realtime` is set),
+/// frames written to it are kept and can be inspected with `written`.  Once all the reports
+/// have been played back, reads fail as if the device had been unplugged.
+pub struct Replayer {
+    inputs: Mutex<VecDeque<Record>>,
+    written: Mutex<Vec<Vec<u8>>>,
+    realtime: bool,
+    start: Instant,
+}
+
+impl Replayer {
+    /// Play back the incoming reports of the given records.
+    pub fn new(records: Vec<Record>) -> Self {
+        Replayer {
+            inputs: Mutex::new(
+                records
+                    .into_iter()
+                    .filter(|r| r.direction == Direction::In)
+                    .collect(),
+            ),
+            written: Mutex::new(Vec::new()),
+            realtime: false,
+            start: Instant::now(),
+        }
+    }
+
+    /// Play back a recording file.
+    pub fn open(path: impl AsRef<std::path::Path>) -> QKResult<Self> {
+        Ok(Replayer::new(read_recording(std::fs::File::open(path)?)?))
+    }
+
+    /// Deliver each report at the moment it was recorded, instead of immediately.
+    pub fn realtime(mut self, realtime: bool) -> Self {
+        self.realtime = realtime;
+        self
+    }
+
+    /// Frames written to the device so far.
+    pub fn written(&self) -> Vec<Vec<u8>> {
+        self.written
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Number of reports not played back yet.
+    pub fn remaining(&self) -> usize {
+        self.inputs.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    fn next_report(&self, buf: &mut [u8], timeout: Option<Duration>) -> HidResult<usize> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        loop {
+            let mut inputs = self.inputs.lock().unwrap_or_else(|e| e.into_inner());
+            let Some(record) = inputs.front() else {
+                return Err(HidError::IoError {
+                    error: io::ErrorKind::NotConnected.into(),
+                });
+            };
+            let due = if self.realtime {
+                self.start + record.at
+            } else {
+                Instant::now()
+            };
+            let now = Instant::now();
+            if due <= now {
+                let record = inputs.pop_front().expect("front exists");
+                let len = record.data.len().min(buf.len());
+                buf[..len].copy_from_slice(&record.data[..len]);
+                return Ok(len);
+            }
+            // Wait without the lock, and check again as another reader may have been faster
+            drop(inputs);
+            match deadline {
+                Some(deadline) if deadline < due => {
+                    std::thread::sleep(deadline.saturating_duration_since(now));
+                    return Ok(0);
+                }
+                _ => std::thread::sleep(due - now),
+            }
+        }
+    }
+}
+
+impl Transport for Replayer {
+    fn write(&self, data: &[u8]) -> HidResult<usize> {
+        self.written
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(data.to_vec());
+        Ok(data.len())
+    }
+
+    fn read(&self, buf: &mut [u8]) -> HidResult<usize> {
+        self.next_report(buf, None)
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> HidResult<usize> {
+        let timeout = (timeout >= 0).then(|| Duration::from_millis(timeout as u64));
+        self.next_report(buf, timeout)
+    }
+
+    fn set_blocking_mode(&self, _blocking: bool) -> HidResult<()> {
+        Ok(())
+    }
+}
+
+/// Fixtures for the tests of the device and everything built on it
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::msgs::{encode_event, Event};
+    use crate::{OpenOptions, QKDevice, Subscriptions};
+
+    /// The device sending the event, right away.
+    pub(crate) fn input(event: &Event) -> Record {
+        Record {
+            at: Duration::ZERO,
+            direction: Direction::In,
+            data: encode_event(event).to_vec(),
+        }
+    }
+
+    /// A device over the replayer, subscribed to the given classes of events.
+    pub(crate) fn open(
+        replayer: Replayer,
+        subscriptions: Subscriptions,
+    ) -> (QKDevice, Arc<Replayer>) {
+        let replayer = Arc::new(replayer);
+        let dev = OpenOptions::new()
+            .subscriptions(subscriptions)
+            .open_transport(Arc::clone(&replayer))
+            .unwrap();
+        (dev, replayer)
+    }
+
+    /// A device replaying the given events, not subscribed to anything.
+    pub(crate) fn replay(events: &[Event]) -> (QKDevice, Arc<Replayer>) {
+        open(
+            Replayer::new(events.iter().map(input).collect()),
+            Subscriptions::none(),
+        )
+    }
+
+    /// A path in the temporary directory, unique to the test run.
+    pub(crate) fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("qk-{}-{}", name, std::process::id()))
+    }
+}
+
+#[cfg(test)]
+mod tests_record {
+    use std::sync::Arc;
+
+    use super::testing::{input, open};
+    use super::*;
+    use crate::msgs::*;
+    use crate::Subscriptions;
+
+    #[test]
+    fn it_should_record_traffic() {
+        let battery = Event::Battery { percent: 42 };
+        let recorder = Recorder::new(Replayer::new(vec![input(&battery)]), Vec::new()).unwrap();
+        recorder.write(&msg_subscribe_to_battery()).unwrap();
+        let mut buf = [0u8; INPUT_REPORT_SIZE];
+        assert_eq!(recorder.read(&mut buf).unwrap(), INPUT_REPORT_SIZE);
+
+        let (_, output) = recorder.into_inner();
+        let records = read_recording(&output[..]).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].direction, Direction::Out);
+        assert_eq!(records[0].data, msg_subscribe_to_battery());
+        assert_eq!(records[1].direction, Direction::In);
+        assert_eq!(records[1].data, encode_event(&battery));
+    }
+
+    #[test]
+    fn it_should_reject_other_files() {
+        assert!(read_recording(&b"nope"[..]).is_err());
+    }
+
+    #[test]
+    fn it_should_replay_a_recording() {
+        let wheel = Event::Wheel {
+            direction: WheelDirection::Left,
+            state: ButtonState::default(),
+        };
+        let battery = Event::Battery { percent: 42 };
+        let (dev, replayer) = open(
+            Replayer::new(vec![input(&wheel), input(&battery)]),
+            Subscriptions::all(),
+        );
+        assert_eq!(
+            replayer.written(),
+            vec![
+                msg_subscribe_to_key_events().to_vec(),
+                msg_subscribe_to_battery().to_vec()
+            ]
+        );
+        assert_eq!(dev.read().unwrap(), Some(wheel));
+        let events = dev.events().collect::<QKResult<Vec<Event>>>().unwrap();
+        assert_eq!(events, vec![battery, Event::Disconnected]);
+    }
+
+    #[test]
+    fn it_should_not_block_while_waiting_in_realtime() {
+        let mut late = input(&Event::Battery { percent: 42 });
+        late.at = Duration::from_millis(300);
+        let replayer = Arc::new(Replayer::new(vec![late]).realtime(true));
+        let reader = {
+            let replayer = Arc::clone(&replayer);
+            std::thread::spawn(move || replayer.read(&mut [0u8; INPUT_REPORT_SIZE]))
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        let start = Instant::now();
+        assert_eq!(replayer.remaining(), 1);
+        assert!(start.elapsed() < Duration::from_millis(100));
+        assert_eq!(reader.join().unwrap().unwrap(), INPUT_REPORT_SIZE);
+        assert_eq!(replayer.remaining(), 0);
+    }
+}
@@ -0,0 +1,167 @@
+use std::time::{Duration, Instant};
+
+use crate::color::Color;
+use crate::command::{Command, OverlayChunk};
+use crate::msgs::{ScreenBrightness, ScreenOrientation, WheelSpeed};
+
+/// A text overlay still shown on the screen
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ActiveOverlay {
+    pub text: String,
+    /// Time left before the overlay disappears
+    pub remaining: Duration,
+}
+
+/// Everything last set on the device through a `QKDevice` (see `QKDevice::snapshot`).
+///
+/// Fields are `None` until the corresponding setting is sent: the device can't be asked
+/// for its current state.
+#[derive(Debug, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceState {
+    pub orientation: Option<ScreenOrientation>,
+    pub brightness: Option<ScreenBrightness>,
+    pub wheel_speed: Option<WheelSpeed>,
+    pub sleep_timeout: Option<u8>,
+    /// Color as requested, before calibration
+    pub ring_color: Option<Color>,
+    pub key_texts: [Option<String>; 8],
+    pub overlay: Option<ActiveOverlay>,
+}
+
+/// Keeps the `DeviceState` up to date with the commands sent to the device.
+#[derive(Debug, Default)]
+pub(crate) struct StateTracker {
+    state: DeviceState,
+    /// Text of the current overlay, when it was shown and for how many seconds
+    overlay: Option<(String, Instant, u8)>,
+}
+
+impl StateTracker {
+    /// Take into account a command successfully sent at `now`.
+    pub(crate) fn command(&mut self, command: &Command, now: Instant) {
+        let state = &mut self.state;
+        match command {
+            Command::Subscribe { .. } => {}
+            Command::SetScreenOrientation { orientation } => state.orientation = Some(*orientation),
+            Command::SetScreenBrightness { level } => state.brightness = Some(*level),
+            Command::SetWheelSpeed { speed } => state.wheel_speed = Some(*speed),
+            Command::SetSleepTimeout { minutes } => state.sleep_timeout = Some(*minutes),
+            Command::SetRingColor { color } => state.ring_color = Some(*color),
+            Command::SetKeyText { key, text } => {
+                if let Some(label) = state.key_texts.get_mut(*key as usize) {
+                    *label = Some(text.clone());
+                }
+            }
+            Command::ShowOverlayText { text, seconds } => {
+                self.overlay = Some((text.clone(), now, *seconds));
+            }
+        }
+    }
+
+    /// Take into account one frame of an overlay successfully sent at `now`.
+    pub(crate) fn overlay_chunk(&mut self, chunk: &OverlayChunk, now: Instant) {
+        match &mut self.overlay {
+            Some((text, _, _)) if !chunk.first => text.push_str(&chunk.text),
+            _ => self.overlay = Some((chunk.text.clone(), now, chunk.seconds)),
+        }
+    }
+
+    /// The text of the overlay shown at `now`, if any.
+    pub(crate) fn overlay(&self, now: Instant) -> Option<ActiveOverlay> {
+        let (text, shown, seconds) = self.overlay.as_ref()?;
+        let expiry = *shown + Duration::from_secs(*seconds as u64);
+        (now < expiry).then(|| ActiveOverlay {
+            text: text.clone(),
+            remaining: expiry - now,
+        })
+    }
+
+    /// The state of the device at `now`.
+    pub(crate) fn snapshot(&self, now: Instant) -> DeviceState {
+        DeviceState {
+            overlay: self.overlay(now),
+            ..self.state.clone()
+        }
+    }
+
+    /// The state of the device, without the overlay.
+    pub(crate) fn state(&self) -> &DeviceState {
+        &self.state
+    }
+}
+
+#[cfg(test)]
+mod tests_state {
+    use super::*;
+
+    #[test]
+    fn it_should_start_unknown() {
+        let tracker = StateTracker::default();
+        assert_eq!(tracker.snapshot(Instant::now()), DeviceState::default());
+    }
+
+    #[test]
+    fn it_should_track_settings() {
+        let mut tracker = StateTracker::default();
+        let now = Instant::now();
+        tracker.command(
+            &Command::SetWheelSpeed {
+                speed: WheelSpeed::Faster,
+            },
+            now,
+        );
+        tracker.command(
+            &Command::SetKeyText {
+                key: 2,
+                text: "copy".to_string(),
+            },
+            now,
+        );
+        tracker.command(
+            &Command::SetKeyText {
+                key: 9,
+                text: "nope".to_string(),
+            },
+            now,
+        );
+        let state = tracker.snapshot(now);
+        assert_eq!(state.wheel_speed, Some(WheelSpeed::Faster));
+        assert_eq!(state.key_texts[2].as_deref(), Some("copy"));
+        assert_eq!(state.key_texts.iter().flatten().count(), 1);
+        assert_eq!(state.orientation, None);
+    }
+
+    #[test]
+    fn it_should_expire_overlays() {
+        let mut tracker = StateTracker::default();
+        let now = Instant::now();
+        tracker.command(
+            &Command::ShowOverlayText {
+                text: "hello".to_string(),
+                seconds: 2,
+            },
+            now,
+        );
+        let overlay = tracker.overlay(now + Duration::from_secs(1)).unwrap();
+        assert_eq!(overlay.text, "hello");
+        assert_eq!(overlay.remaining, Duration::from_secs(1));
+        assert_eq!(tracker.overlay(now + Duration::from_secs(2)), None);
+    }
+
+    #[test]
+    fn it_should_assemble_overlay_chunks() {
+        let mut tracker = StateTracker::default();
+        let now = Instant::now();
+        let chunk = |first, text: &str| OverlayChunk {
+            first,
+            seconds: 3,
+            text: text.to_string(),
+            has_more: first,
+        };
+        tracker.overlay_chunk(&chunk(true, "Disco, d"), now);
+        tracker.overlay_chunk(&chunk(false, "isco!"), now);
+        assert_eq!(tracker.overlay(now).unwrap().text, "Disco, disco!");
+    }
+}
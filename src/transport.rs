@@ -0,0 +1,55 @@
+use std::sync::Arc;
+
+use hidapi::{HidDevice, HidResult};
+
+/// The link with a Quick Keys device: where frames are written to and reports read from.
+///
+/// Implemented by `hidapi::HidDevice`, and by `Recorder` and `Replayer` to record a session
+/// or to play it back without the actual device.
+pub trait Transport: Send {
+    /// Write a frame (starting with the report id).
+    fn write(&self, data: &[u8]) -> HidResult<usize>;
+    /// Read an input report, returning the number of bytes read.
+    fn read(&self, buf: &mut [u8]) -> HidResult<usize>;
+    /// Read an input report waiting at most `timeout` milliseconds (-1 waits forever).
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> HidResult<usize>;
+    /// Set the blocking mode of `read`.
+    fn set_blocking_mode(&self, blocking: bool) -> HidResult<()>;
+}
+
+impl Transport for HidDevice {
+    fn write(&self, data: &[u8]) -> HidResult<usize> {
+        HidDevice::write(self, data)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> HidResult<usize> {
+        HidDevice::read(self, buf)
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> HidResult<usize> {
+        HidDevice::read_timeout(self, buf, timeout)
+    }
+
+    fn set_blocking_mode(&self, blocking: bool) -> HidResult<()> {
+        HidDevice::set_blocking_mode(self, blocking)
+    }
+}
+
+/// Share a transport, e.g. to keep inspecting a `Replayer` handed over to a `QKDevice`.
+impl<T: Transport + Sync> Transport for Arc<T> {
+    fn write(&self, data: &[u8]) -> HidResult<usize> {
+        (**self).write(data)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> HidResult<usize> {
+        (**self).read(buf)
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> HidResult<usize> {
+        (**self).read_timeout(buf, timeout)
+    }
+
+    fn set_blocking_mode(&self, blocking: bool) -> HidResult<()> {
+        (**self).set_blocking_mode(blocking)
+    }
+}
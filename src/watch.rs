@@ -0,0 +1,169 @@
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use inotify::{Inotify, WatchMask};
+
+use crate::error::QKError;
+use crate::layers::{LayerSwitcher, Profile};
+use crate::{QKDevice, QKResult};
+
+/// Seconds the error overlay is shown when a profile can't be reloaded
+const ERROR_OVERLAY_SECONDS: u8 = 3;
+
+/// Watch a profile file and reload it when it changes (Linux only).
+///
+/// The parent directory is watched rather than the file itself, so that editors replacing
+/// the file on save are followed too.  Checking for changes never blocks: call `reload`
+/// between reads to keep handling the events of the device.
+///
+/// ```no_run
+/// # use xencelabs_quick_keys::*;
+/// # fn main() -> QKResult<()> {
+/// let dev = QKDevice::open(hidapi::HidApi::new()?, ConnectionMode::Auto)?;
+/// let mut watcher = ProfileWatcher::new("profile.toml")?;
+/// let mut layers = LayerSwitcher::new(Profile::load(watcher.path())?);
+/// layers.activate(&dev)?;
+/// loop {
+///     if let Some(event) = dev.read_timeout(100)? {
+///         layers.handle(&dev, &event)?;
+///     }
+///     watcher.reload(&dev, &mut layers)?;
+/// }
+/// # }
+/// ```
+pub struct ProfileWatcher {
+    path: PathBuf,
+    name: OsString,
+    inotify: Inotify,
+    buffer: Vec<u8>,
+}
+
+impl ProfileWatcher {
+    /// Start watching the given profile file.
+    pub fn new(path: impl AsRef<Path>) -> QKResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let name = path
+            .file_name()
+            .ok_or_else(|| QKError::QKParseError(format!("{} is not a file", path.display())))?
+            .to_os_string();
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let inotify = Inotify::init()?;
+        // Not CREATE: the file is still empty then, CLOSE_WRITE follows once it is written
+        inotify
+            .watches()
+            .add(dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)?;
+        Ok(ProfileWatcher {
+            path,
+            name,
+            inotify,
+            buffer: vec![0; 4096],
+        })
+    }
+
+    /// The watched file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the file was written or replaced since the last call.
+    pub fn changed(&mut self) -> QKResult<bool> {
+        let mut changed = false;
+        loop {
+            match self.inotify.read_events(&mut self.buffer) {
+                Ok(events) => {
+                    let name = &self.name;
+                    changed |= events.into_iter().any(|e| e.name == Some(name.as_os_str()));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(changed),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// If the file changed, load it again and switch the layers to the new profile (see
+    /// `LayerSwitcher::set_profile`).
+    ///
+    /// An invalid profile is logged and reported on the device as an overlay, and the
+    /// previous one stays active.  Returns whether a new profile was applied.
+    pub fn reload(&mut self, dev: &QKDevice, layers: &mut LayerSwitcher) -> QKResult<bool> {
+        if !self.changed()? {
+            return Ok(false);
+        }
+        match Profile::load(&self.path) {
+            Ok(profile) => {
+                log::info!("reloading profile {}", self.path.display());
+                layers.set_profile(dev, profile)?;
+                Ok(true)
+            }
+            Err(e @ (QKError::QKParseError(_) | QKError::QKIoError(_))) => {
+                log::error!("invalid profile, keeping the previous one: {}", e);
+                dev.show_overlay_text(&error_overlay(&e), ERROR_OVERLAY_SECONDS)?;
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Short description of a profile error, fitting in an overlay.
+fn error_overlay(e: &QKError) -> String {
+    let message = e.to_string();
+    let line = message.find("line ").and_then(|i| {
+        let digits = message[i + 5..]
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .collect::<String>();
+        (!digits.is_empty()).then_some(digits)
+    });
+    match line {
+        Some(line) => format!("Profile error line {}", line),
+        None => "Profile error".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests_watch {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::record::testing::{replay, temp_path};
+
+    fn profile(label: &str) -> String {
+        format!(
+            r#"{{"layers": [{{"name": "a", "labels": ["{}"]}}]}}"#,
+            label
+        )
+    }
+
+    #[test]
+    fn it_should_reload_changed_profiles() {
+        let dir = temp_path("watch");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("profile.json");
+        std::fs::write(&path, profile("old")).unwrap();
+
+        let (dev, _) = replay(&[]);
+        let mut watcher = ProfileWatcher::new(&path).unwrap();
+        let mut layers = LayerSwitcher::new(Profile::load(&path).unwrap());
+        layers.activate(&dev).unwrap();
+        assert!(!watcher.reload(&dev, &mut layers).unwrap());
+
+        std::fs::write(dir.join("unrelated.json"), "").unwrap();
+        std::fs::write(&path, profile("new")).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(watcher.reload(&dev, &mut layers).unwrap());
+        assert_eq!(dev.key_text(0).as_deref(), Some("new"));
+
+        std::fs::write(&path, "{\n\"layers\": []}").unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!watcher.reload(&dev, &mut layers).unwrap());
+        assert_eq!(layers.layer().unwrap().labels[0], "new");
+        assert_eq!(dev.overlay().unwrap().text, "Profile error line 2");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}